@@ -1,5 +1,67 @@
+/// Messages of `redmine_api` with a JSON mapping where every field is optional, listed one
+/// by one as enums can't take `#[serde(default)]`.
+const JSON_MESSAGES: &[&str] = &[
+    "ReportRequest",
+    "ReportPreset",
+    "ListPresetsResponse",
+    "DeletePresetRequest",
+    "TimeEntryFilter",
+    "ReportResponse",
+    "ListRequest",
+    "User",
+    "ListUsersResponse",
+    "Project",
+    "ListProjectsResponse",
+    "Activity",
+    "ListActivitiesResponse",
+    "ReportJob",
+    "ReportJobRequest",
+    "AuditEntry",
+    "AuditLogRequest",
+    "AuditLogResponse",
+];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/redmine_api.proto")?;
-    println!("cargo:rerun-if-changed=proto/redmine_api.proto");
+    let mut config = tonic_build::configure()
+        // JSON mapping for the HTTP gateway
+        .type_attribute(
+            ".redmine_api",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        );
+    for message in JSON_MESSAGES {
+        config = config.type_attribute(format!(".redmine_api.{}", message), "#[serde(default)]");
+    }
+
+    config
+        .type_attribute(
+            ".google.type",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .field_attribute(
+            ".redmine_api.ReportRequest.from_time",
+            "#[serde(with = \"crate::controller::json::optional_timestamp\")]",
+        )
+        .field_attribute(
+            ".redmine_api.ReportRequest.to_time",
+            "#[serde(with = \"crate::controller::json::optional_timestamp\")]",
+        )
+        .field_attribute(
+            ".redmine_api.ReportJob.submitted_at",
+            "#[serde(with = \"crate::controller::json::optional_timestamp\")]",
+        )
+        .field_attribute(
+            ".redmine_api.ReportJob.finished_at",
+            "#[serde(with = \"crate::controller::json::optional_timestamp\")]",
+        )
+        .field_attribute(
+            ".redmine_api.AuditEntry.time",
+            "#[serde(with = \"crate::controller::json::optional_timestamp\")]",
+        )
+        .field_attribute(
+            ".redmine_api.AuditLogRequest.since",
+            "#[serde(with = \"crate::controller::json::optional_timestamp\")]",
+        )
+        .compile(&["proto/redmine_api.proto"], &["proto"])?;
+    println!("cargo:rerun-if-changed=proto");
     Ok(())
 }
@@ -0,0 +1,500 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use time::OffsetDateTime;
+use tonic::{Request, Response, Status};
+
+use super::{
+    auth::Caller,
+    json::{datetime_to_timestamp, timestamp_to_datetime},
+    redmine_service::{AuditEntry, AuditLogRequest, ReportRequest, ReportResponse},
+    validation::ValidRequest,
+};
+
+/// Latest entries kept in memory, older ones are only found in the audit file, if any.
+const MEMORY_ENTRIES: usize = 10_000;
+
+#[derive(Debug)]
+struct Store {
+    /// The latest entries, so that most queries need no I/O.
+    entries: VecDeque<AuditEntry>,
+    capacity: usize,
+    /// Latest call time of the entries dropped from `entries`. Entries are recorded once
+    /// their call finishes, so this is not the time of the last one dropped.
+    dropped_until: Option<OffsetDateTime>,
+    /// JSON lines, only ever appended to.
+    file: Option<(PathBuf, File)>,
+}
+
+impl Store {
+    fn new(capacity: usize, file: Option<(PathBuf, File)>) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            dropped_until: None,
+            file,
+        }
+    }
+
+    fn keep(&mut self, entry: AuditEntry) {
+        self.entries.push_back(entry);
+        if self.entries.len() > self.capacity {
+            let dropped = self
+                .entries
+                .pop_front()
+                .as_ref()
+                .and_then(time)
+                // entries without a time only match queries without `since`
+                .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+            self.dropped_until = self.dropped_until.max(Some(dropped));
+        }
+    }
+
+    fn append(&mut self, entry: AuditEntry) -> Result<()> {
+        if let Some((path, file)) = &mut self.file {
+            let line = serde_json::to_string(&entry)? + "\n";
+            file.write_all(line.as_bytes())
+                .and_then(|_| file.sync_data())
+                .with_context(|| format!("write {} failed", path.display()))?;
+        }
+        self.keep(entry);
+
+        Ok(())
+    }
+
+    /// Entries matching `matches` in the order they were recorded, read from the file when
+    /// some of them may have been dropped from memory.
+    fn find(
+        &self,
+        since: Option<OffsetDateTime>,
+        matches: impl Fn(&AuditEntry) -> bool,
+    ) -> Result<Vec<AuditEntry>> {
+        let in_memory = match (since, self.dropped_until) {
+            (_, None) => true,
+            (Some(since), Some(dropped_until)) => since > dropped_until,
+            (None, Some(_)) => false,
+        };
+
+        match &self.file {
+            Some((path, _)) if !in_memory => {
+                let mut found = Vec::new();
+                scan(path, |entry| {
+                    if matches(&entry) {
+                        found.push(entry);
+                    }
+                })?;
+                Ok(found)
+            }
+            _ => Ok(self
+                .entries
+                .iter()
+                .filter(|entry| matches(entry))
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+/// Record of every call handing out reports, kept in memory only without a file.
+///
+/// Calls the authenticator rejects never reach a handler, so they are not recorded.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    store: Arc<Mutex<Store>>,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            store: Arc::new(Mutex::new(Store::new(MEMORY_ENTRIES, None))),
+        }
+    }
+}
+
+impl AuditLog {
+    /// Appends to `path`, which is created if missing, after reading the entries it holds.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        Self::open_keeping(path, MEMORY_ENTRIES)
+    }
+
+    fn open_keeping(path: PathBuf, capacity: usize) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("open {} failed", path.display()))?;
+
+        let mut store = Store::new(capacity, None);
+        let mut count = 0;
+        let complete = scan(&path, |entry| {
+            count += 1;
+            store.keep(entry);
+        })?;
+        if !complete {
+            // ends the line a crash left incomplete, which would garble the next entry
+            file.write_all(b"\n")
+                .with_context(|| format!("write {} failed", path.display()))?;
+        }
+        info!(
+            "Audit log appended to {}, holding {} entries",
+            path.display(),
+            count
+        );
+
+        store.file = Some((path, file));
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+        })
+    }
+
+    /// Records the entry of a finished call. Reports are only handed out once the audit log
+    /// holds them, so a successful call fails if its entry could not be written.
+    pub async fn record<T>(
+        &self,
+        entry: AuditEntry,
+        result: Result<T, Status>,
+    ) -> Result<T, Status> {
+        let store = self.store.clone();
+        let appended = tokio::task::spawn_blocking(move || store.lock().unwrap().append(entry))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|appended| appended);
+
+        match appended {
+            Ok(()) => result,
+            Err(err) => {
+                error!("Writing the audit log failed: {:#}", err);
+                result.and(Err(Status::internal(format!(
+                    "writing the audit log failed: {:#}",
+                    err
+                ))))
+            }
+        }
+    }
+
+    /// Entries matching `request` in the order they were recorded.
+    pub async fn query(&self, request: &AuditLogRequest) -> Result<Vec<AuditEntry>, Status> {
+        let since = match &request.since {
+            Some(since) => Some(
+                timestamp_to_datetime(since)
+                    .map_err(|err| Status::invalid_argument(format!("since: {}", err)))?,
+            ),
+            None => None,
+        };
+        let request = request.clone();
+        let matches = move |entry: &AuditEntry| {
+            (request.caller.is_empty() || entry.caller == request.caller)
+                && (request.user_id == 0
+                    || entry.user_id.contains(&request.user_id)
+                    || entry.reported_user_id.contains(&request.user_id))
+                && since.is_none_or(|since| time(entry).is_some_and(|time| time >= since))
+        };
+
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || store.lock().unwrap().find(since, matches))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|found| found)
+            .map_err(|err| {
+                error!("Reading the audit log failed: {:#}", err);
+                Status::internal(format!("reading the audit log failed: {:#}", err))
+            })
+    }
+}
+
+fn time(entry: &AuditEntry) -> Option<OffsetDateTime> {
+    entry
+        .time
+        .as_ref()
+        .and_then(|time| timestamp_to_datetime(time).ok())
+}
+
+/// Calls `f` with every entry of `path`, returns whether its last line is complete.
+fn scan(path: &Path, mut f: impl FnMut(AuditEntry)) -> Result<bool> {
+    let file = File::open(path).with_context(|| format!("open {} failed", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let mut line = Vec::new();
+    let mut number = 0;
+    let mut complete = true;
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .with_context(|| format!("read {} failed", path.display()))?;
+        if read == 0 {
+            return Ok(complete);
+        }
+        number += 1;
+        complete = line.ends_with(b"\n");
+
+        match serde_json::from_slice(&line) {
+            Ok(entry) => f(entry),
+            // a crash may leave the last line incomplete
+            Err(err) => warn!(
+                "Skipped malformed line {} of the audit log {}: {}",
+                number,
+                path.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Starts the entry of a call, before anything about it is checked.
+pub fn entry<T>(rpc: &str, request: &Request<T>, request_id: &str) -> AuditEntry {
+    AuditEntry {
+        time: Some(datetime_to_timestamp(OffsetDateTime::now_utc())),
+        request_id: request_id.to_string(),
+        rpc: rpc.to_string(),
+        caller: request
+            .extensions()
+            .get::<Caller>()
+            .map(|caller| caller.name.clone())
+            .unwrap_or_default(),
+        remote_addr: super::remote_addr(request)
+            .map(|addr| addr.to_string())
+            .unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+/// Takes the users as requested, before the preset is applied.
+pub fn requested(entry: &mut AuditEntry, report: &ReportRequest) {
+    entry.user_id = report.user_id.clone();
+    entry.group_id = report.group_id.clone();
+    entry.login = report.login.clone();
+    entry.query_id = report.query_id;
+    entry.preset = report.preset.clone();
+    entry.impersonate = report.impersonate;
+}
+
+/// Takes the users and period of the request once its preset is applied and it is valid.
+pub fn validated(entry: &mut AuditEntry, request: &ValidRequest) {
+    entry.user_id = request.user_ids.clone();
+    entry.group_id = request.group_ids.clone();
+    entry.login = request.logins.clone();
+    entry.query_id = request.filter.query_id.unwrap_or_default();
+    entry.impersonate = request.impersonate;
+    entry.from = request.filter.from.to_string();
+    entry.to = request.filter.to.to_string();
+}
+
+/// Completes the entry with the outcome of the call and the users whose reports it returned.
+pub fn finished<T>(
+    mut entry: AuditEntry,
+    result: &Result<Response<T>, Status>,
+    reported_users: impl FnOnce(&T) -> Vec<u64>,
+) -> AuditEntry {
+    match result {
+        Ok(response) => {
+            entry.outcome = format!("{:?}", tonic::Code::Ok);
+            entry.reported_user_id = reported_users(response.get_ref());
+        }
+        Err(status) => {
+            entry.outcome = format!("{:?}", status.code());
+            entry.error = status.message().to_string();
+        }
+    }
+
+    entry
+}
+
+pub fn reported_users(response: &ReportResponse) -> Vec<u64> {
+    response
+        .reports
+        .iter()
+        .map(|report| report.user_id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::controller::{auth::AllowedUsers, redmine_service::report_response::PerUserReport};
+
+    fn call(caller: &str, user_ids: Vec<u64>, result: Result<Vec<u64>, Status>) -> AuditEntry {
+        let mut request = Request::new(ReportRequest {
+            user_id: user_ids,
+            ..Default::default()
+        });
+        request.extensions_mut().insert(Caller {
+            name: caller.to_string(),
+            allowed_users: AllowedUsers::Any,
+        });
+
+        let mut entry = entry("GenerateReport", &request, "a1b2c3");
+        requested(&mut entry, request.get_ref());
+        finished(
+            entry,
+            &result.map(|user_ids| {
+                Response::new(ReportResponse {
+                    reports: user_ids
+                        .into_iter()
+                        .map(|user_id| PerUserReport {
+                            user_id,
+                            ..Default::default()
+                        })
+                        .collect(),
+                })
+            }),
+            reported_users,
+        )
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn query_audit_file() {
+        let path = temp_path("audit");
+
+        let audit = AuditLog::open(path.clone()).unwrap();
+        let record = |entry| audit.record(entry, Ok(()));
+        record(call("backend", vec![12], Ok(vec![12])))
+            .await
+            .unwrap();
+        record(call(
+            "ci",
+            vec![15],
+            Err(Status::permission_denied("ci may not")),
+        ))
+        .await
+        .unwrap();
+        record(call("ci", vec![], Ok(vec![12, 15]))).await.unwrap();
+        assert_eq!(
+            audit
+                .query(&AuditLogRequest::default())
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // entries survive a restart
+        let audit = AuditLog::open(path.clone()).unwrap();
+        let query = |caller: &str, user_id| {
+            let audit = audit.clone();
+            let request = AuditLogRequest {
+                caller: caller.to_string(),
+                user_id,
+                ..Default::default()
+            };
+            async move { audit.query(&request).await.unwrap() }
+        };
+
+        assert_eq!(query("", 0).await.len(), 3);
+        let by_ci = query("ci", 0).await;
+        assert_eq!(by_ci.len(), 2);
+        assert_eq!(by_ci[0].rpc, "GenerateReport");
+        assert_eq!(by_ci[0].outcome, "PermissionDenied");
+        assert_eq!(by_ci[0].error, "ci may not");
+        assert_eq!(by_ci[0].request_id, "a1b2c3");
+        assert_eq!(by_ci[1].reported_user_id, vec![12, 15]);
+        assert_eq!(
+            query("", 12)
+                .await
+                .iter()
+                .map(|entry| entry.caller.as_str())
+                .collect::<Vec<_>>(),
+            vec!["backend", "ci"]
+        );
+
+        let since = audit
+            .query(&AuditLogRequest {
+                since: Some(datetime_to_timestamp(
+                    OffsetDateTime::now_utc() + time::Duration::hours(1),
+                )),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(since.unwrap().len(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fail_calls_missing_from_audit_file() {
+        let path = temp_path("audit-read-only");
+        std::fs::write(&path, "").unwrap();
+        // writes to a file opened for reading fail
+        let audit = AuditLog {
+            store: Arc::new(Mutex::new(Store::new(
+                MEMORY_ENTRIES,
+                Some((path.clone(), File::open(&path).unwrap())),
+            ))),
+        };
+
+        let entry = || call("backend", vec![12], Ok(vec![12]));
+        let status = audit.record(entry(), Ok(())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        // the error of a failed call is kept
+        let status = audit
+            .record(entry(), Err::<(), _>(Status::not_found("no such job")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert!(audit
+            .query(&AuditLogRequest::default())
+            .await
+            .unwrap()
+            .is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn query_entries_dropped_from_memory() {
+        let path = temp_path("audit-dropped");
+        let first = serde_json::to_string(&call("first", vec![12], Ok(vec![12]))).unwrap();
+        // a crash left the last line incomplete
+        std::fs::write(&path, format!("{}\n{{\"caller\":\"to", first)).unwrap();
+
+        let audit = AuditLog::open_keeping(path.clone(), 2).unwrap();
+        for caller in ["a", "b", "c"] {
+            audit
+                .record(call(caller, vec![12], Ok(vec![12])), Ok(()))
+                .await
+                .unwrap();
+        }
+        let callers = |entries: Vec<AuditEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.caller)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            callers(audit.query(&AuditLogRequest::default()).await.unwrap()),
+            vec!["first", "a", "b", "c"]
+        );
+        assert_eq!(
+            callers(
+                audit
+                    .query(&AuditLogRequest {
+                        caller: "a".to_string(),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap()
+            ),
+            vec!["a"]
+        );
+        assert_eq!(audit.store.lock().unwrap().entries.len(), 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 5);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+use log::debug;
+use tonic::{service::Interceptor, Request, Status};
+
+const AUTHORIZATION_METADATA: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// Which Redmine users a caller may request reports for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AllowedUsers {
+    Any,
+    Only(HashSet<u64>),
+}
+
+/// Authenticated client, attached to request extensions by [`Authenticator`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub name: String,
+    pub allowed_users: AllowedUsers,
+}
+
+impl Caller {
+    fn anonymous() -> Self {
+        Self {
+            name: "anonymous".to_string(),
+            allowed_users: AllowedUsers::Any,
+        }
+    }
+
+    pub fn allows(&self, user_id: u64) -> bool {
+        match &self.allowed_users {
+            AllowedUsers::Any => true,
+            AllowedUsers::Only(allowed) => allowed.contains(&user_id),
+        }
+    }
+
+    /// Fails with `permission_denied` unless the caller may request reports for everyone.
+    pub fn check_unrestricted(&self, action: &str) -> Result<(), Status> {
+        match self.allowed_users {
+            AllowedUsers::Any => Ok(()),
+            AllowedUsers::Only(_) => Err(Status::permission_denied(format!(
+                "{} is not allowed to {}",
+                self.name, action
+            ))),
+        }
+    }
+
+    /// Fails with `permission_denied` if any of `user_ids` is out of the caller's reach.
+    pub fn check_users(&self, user_ids: &[u64]) -> Result<(), Status> {
+        let allowed = match &self.allowed_users {
+            AllowedUsers::Any => return Ok(()),
+            AllowedUsers::Only(allowed) => allowed,
+        };
+
+        let denied = user_ids
+            .iter()
+            .filter(|user_id| !allowed.contains(user_id))
+            .unique()
+            .join(", ");
+
+        if denied.is_empty() {
+            Ok(())
+        } else {
+            Err(Status::permission_denied(format!(
+                "{} is not allowed to request reports for users: {}",
+                self.name, denied
+            )))
+        }
+    }
+}
+
+struct Token {
+    secret: String,
+    caller: Caller,
+}
+
+/// gRPC interceptor validating `authorization: Bearer <token>` metadata.
+#[derive(Clone)]
+pub struct Authenticator {
+    tokens: Option<Arc<RwLock<Vec<Token>>>>,
+}
+
+impl std::fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authenticator")
+            .field(
+                "callers",
+                &self.tokens.as_ref().map(|tokens| {
+                    tokens
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .map(|token| token.caller.name.clone())
+                        .collect_vec()
+                }),
+            )
+            .finish()
+    }
+}
+
+impl Authenticator {
+    /// Lets every request through as an anonymous caller allowed to see all users.
+    pub fn disabled() -> Self {
+        Self { tokens: None }
+    }
+
+    /// Parses a token list in the `name:token:user_ids` form, separated by `;`.
+    ///
+    /// `user_ids` is either a comma separated list of Redmine user ids or `*` for any user,
+    /// e.g. `backend:s3cr3t:12,15;ci:t0k3n:*`.
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: Some(Arc::new(RwLock::new(parse_tokens(spec)?))),
+        })
+    }
+
+    /// Swaps the token list of every clone for a rotated one, keeping it on errors.
+    pub fn reload(&self, spec: &str) -> Result<()> {
+        let tokens = parse_tokens(spec)?;
+
+        match &self.tokens {
+            Some(current) => *current.write().unwrap() = tokens,
+            None => bail!("authentication is disabled"),
+        }
+        Ok(())
+    }
+
+    fn authenticate(&self, secret: &str) -> Option<Caller> {
+        self.tokens
+            .as_ref()?
+            .read()
+            .unwrap()
+            .iter()
+            .fold(None, |found, token| {
+                // walk the whole list to not leak the matching position through timings
+                match constant_time_eq(token.secret.as_bytes(), secret.as_bytes()) {
+                    true => Some(token),
+                    false => found,
+                }
+            })
+            .map(|token| token.caller.clone())
+    }
+}
+
+fn parse_tokens(spec: &str) -> Result<Vec<Token>> {
+    let tokens = spec
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .enumerate()
+        .map(|(index, entry)| {
+            // entries are referred to by position, they contain the secret
+            let (name, secret, users) =
+                entry.splitn(3, ':').collect_tuple().with_context(|| {
+                    format!("token entry #{} is not name:token:user_ids", index + 1)
+                })?;
+
+            if name.is_empty() || secret.is_empty() {
+                bail!("token entry #{} has an empty name or token", index + 1);
+            }
+
+            let allowed_users = match users.trim() {
+                "*" => AllowedUsers::Any,
+                users => AllowedUsers::Only(
+                    users
+                        .split(',')
+                        .map(|user_id| {
+                            user_id.trim().parse().with_context(|| {
+                                format!("invalid user id '{}' for {}", user_id, name)
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                ),
+            };
+
+            Ok(Token {
+                secret: secret.to_string(),
+                caller: Caller {
+                    name: name.to_string(),
+                    allowed_users,
+                },
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if tokens.is_empty() {
+        bail!("token list is empty");
+    }
+
+    Ok(tokens)
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let caller = match self.tokens {
+            None => Caller::anonymous(),
+            Some(_) => {
+                let secret = request
+                    .metadata()
+                    .get(AUTHORIZATION_METADATA)
+                    .ok_or_else(|| Status::unauthenticated("missing authorization metadata"))?
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+                    .ok_or_else(|| Status::unauthenticated("expected a bearer token"))?;
+
+                self.authenticate(secret.trim())
+                    .ok_or_else(|| Status::unauthenticated("invalid bearer token"))?
+            }
+        };
+
+        debug!("Authenticated caller {}", caller.name);
+        request.extensions_mut().insert(caller);
+
+        Ok(request)
+    }
+}
+
+fn constant_time_eq(lhs: &[u8], rhs: &[u8]) -> bool {
+    lhs.len() == rhs.len() && lhs.iter().zip(rhs).fold(0, |acc, (l, r)| acc | (l ^ r)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn request_with_token(token: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(AUTHORIZATION_METADATA, token.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn parse_token_spec() {
+        let auth = Authenticator::from_spec("backend:secret:1, 2;ci:token:*;").unwrap();
+
+        assert_eq!(
+            auth.authenticate("secret"),
+            Some(Caller {
+                name: "backend".to_string(),
+                allowed_users: AllowedUsers::Only([1, 2].into_iter().collect()),
+            })
+        );
+        assert_eq!(
+            auth.authenticate("token")
+                .map(|caller| caller.allowed_users),
+            Some(AllowedUsers::Any)
+        );
+        assert_eq!(auth.authenticate("unknown"), None);
+
+        assert!(Authenticator::from_spec("").is_err());
+        assert!(Authenticator::from_spec("backend:secret").is_err());
+        assert!(Authenticator::from_spec("backend:secret:x").is_err());
+
+        let clone = auth.clone();
+        clone.reload("rotated:s3cr3t:*").unwrap();
+        assert_eq!(auth.authenticate("secret"), None);
+        assert_eq!(
+            auth.authenticate("s3cr3t").map(|caller| caller.name),
+            Some("rotated".to_string())
+        );
+        assert!(auth.reload("rotated").is_err());
+        assert!(auth.authenticate("s3cr3t").is_some());
+        assert!(Authenticator::disabled().reload("ci:token:*").is_err());
+    }
+
+    #[test]
+    fn intercept_requests() {
+        let mut auth = Authenticator::from_spec("backend:secret:1,2").unwrap();
+
+        let request = auth.call(request_with_token("Bearer secret")).unwrap();
+        let caller = request.extensions().get::<Caller>().unwrap();
+        assert!(caller.check_users(&[1, 2]).is_ok());
+        assert_eq!(
+            caller.check_users(&[1, 3, 4]).unwrap_err().code(),
+            tonic::Code::PermissionDenied
+        );
+
+        for token in ["Bearer wrong", "secret"] {
+            assert_eq!(
+                auth.call(request_with_token(token)).unwrap_err().code(),
+                tonic::Code::Unauthenticated
+            );
+        }
+        assert_eq!(
+            auth.call(Request::new(())).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+
+        let request = Authenticator::disabled().call(Request::new(())).unwrap();
+        assert_eq!(
+            request.extensions().get::<Caller>(),
+            Some(&Caller::anonymous())
+        );
+    }
+}
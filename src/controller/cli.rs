@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
+use log::info;
+use tonic::Request;
+
+use super::{
+    auth::{AllowedUsers, Caller},
+    gateway::{self, Format},
+    presets::FORMATS,
+    redmine_service::{reports_server::Reports, ReportRequest, ReportResponse},
+    ReportService,
+};
+use crate::view::document;
+
+/// Report request arguments shared by the `report` and `client` subcommands.
+#[derive(Debug, Default, clap::Args)]
+pub struct RequestArgs {
+    /// Redmine group whose members get a report, may be repeated
+    #[arg(long = "group")]
+    pub groups: Vec<u64>,
+    /// First day, a YYYY-MM-DD date or RFC 3339 timestamp
+    #[arg(long)]
+    pub from: Option<String>,
+    /// Last day, a YYYY-MM-DD date or RFC 3339 timestamp
+    #[arg(long)]
+    pub to: Option<String>,
+    /// Named period like last_week or an ISO week like 2026-W41
+    #[arg(long, conflicts_with_all = ["from", "to"])]
+    pub period: Option<String>,
+    /// Saved Redmine time entry query
+    #[arg(long)]
+    pub query: Option<u64>,
+    /// Report preset providing the arguments not given
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// Generate each report as its user, needs an admin API key
+    #[arg(long)]
+    pub impersonate: bool,
+    /// Output format, defaults to the preset format or markdown (alias md)
+    #[arg(long, value_parser = format_parser())]
+    pub format: Option<String>,
+}
+
+/// Accepts the preset formats, and `md` for `markdown`.
+fn format_parser() -> impl TypedValueParser<Value = String> {
+    PossibleValuesParser::new(FORMATS.map(|format| match format {
+        "markdown" => PossibleValue::new(format).alias("md"),
+        _ => PossibleValue::new(format),
+    }))
+    .map(|format| match format.as_str() {
+        "md" => "markdown".to_string(),
+        _ => format,
+    })
+}
+
+impl RequestArgs {
+    /// Request without users, they are given differently by each subcommand.
+    pub fn request(&self) -> ReportRequest {
+        ReportRequest {
+            group_id: self.groups.clone(),
+            generate_from_ts: self.from.clone().unwrap_or_default(),
+            generate_to_ts: self.to.clone().unwrap_or_default(),
+            period: self.period.clone().unwrap_or_default(),
+            query_id: self.query.unwrap_or_default(),
+            preset: self.preset.clone().unwrap_or_default(),
+            impersonate: self.impersonate,
+            ..Default::default()
+        }
+    }
+}
+
+/// Arguments of the `report` subcommand, mirroring the fields of `ReportRequest`.
+#[derive(Debug, Default, clap::Args)]
+pub struct ReportArgs {
+    /// Redmine user id, may be repeated
+    #[arg(long = "user")]
+    pub users: Vec<u64>,
+    /// Login or email of a user, may be repeated
+    #[arg(long = "login")]
+    pub logins: Vec<String>,
+    #[command(flatten)]
+    pub request: RequestArgs,
+    /// File to write the report to instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+impl ReportArgs {
+    fn request(&self) -> ReportRequest {
+        ReportRequest {
+            user_id: self.users.clone(),
+            login: self.logins.clone(),
+            ..self.request.request()
+        }
+    }
+}
+
+/// Renders a whole report as the document printed or saved by the subcommands.
+pub(super) fn render(response: &ReportResponse, format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(response)? + "\n",
+        Format::Markdown => gateway::markdown(response),
+        Format::Html => document::html(&gateway::markdown(response)),
+    })
+}
+
+/// Generates a report in-process, going through the same checks as a gRPC request.
+pub async fn report(service: &ReportService, args: ReportArgs) -> Result<()> {
+    let format = match &args.request.format {
+        Some(format) => format.clone(),
+        None => match &args.request.preset {
+            Some(preset) => {
+                service
+                    .presets()
+                    .get(preset)
+                    .map_err(|status| anyhow!("{}", status.message()))?
+                    .format
+            }
+            None => String::new(),
+        },
+    };
+    let format = Format::from_preset(&format).unwrap_or(Format::Markdown);
+
+    // whoever runs the binary already holds the Redmine API key
+    let mut request = Request::new(args.request());
+    request.extensions_mut().insert(Caller {
+        name: "cli".to_string(),
+        allowed_users: AllowedUsers::Any,
+    });
+    let response = service
+        .generate_report(request)
+        .await
+        .map_err(|status| anyhow!("{}", status.message()))?
+        .into_inner();
+
+    let document = render(&response, format)?;
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, document)
+                .with_context(|| format!("write {} failed", path.display()))?;
+            info!("Report written to {}", path.display());
+        }
+        None => print!("{}", document),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::config::{Args, Command};
+
+    #[test]
+    fn parse_report_args() {
+        let args = Args::try_parse_from([
+            "redmine-service",
+            "report",
+            "--user",
+            "12",
+            "--user",
+            "15",
+            "--period",
+            "last_week",
+            "--format",
+            "json",
+            "--redmine-url",
+            "https://redmine.example.com",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.redmine_url.as_deref(),
+            Some("https://redmine.example.com")
+        );
+
+        let report = match args.command {
+            Some(Command::Report(report)) => report,
+            command => panic!("expected the report subcommand, got {:?}", command),
+        };
+        assert_eq!(
+            report.request(),
+            ReportRequest {
+                user_id: vec![12, 15],
+                period: "last_week".to_string(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(report.request.format.as_deref(), Some("json"));
+
+        let args = Args::try_parse_from(["redmine-service", "report", "--format", "md"]).unwrap();
+        match args.command {
+            Some(Command::Report(report)) => {
+                assert_eq!(report.request.format.as_deref(), Some("markdown"))
+            }
+            command => panic!("expected the report subcommand, got {:?}", command),
+        }
+
+        for args in [
+            ["redmine-service", "report", "--format", "pdf"].as_slice(),
+            &[
+                "redmine-service",
+                "report",
+                "--period",
+                "today",
+                "--from",
+                "2026-01-01",
+            ],
+        ] {
+            assert!(Args::try_parse_from(args).is_err());
+        }
+    }
+}
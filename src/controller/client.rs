@@ -0,0 +1,341 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use itertools::Itertools;
+use log::info;
+use tonic::{
+    codegen::InterceptedService,
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    transport::{Certificate, Channel, ClientTlsConfig},
+    Request, Status,
+};
+
+use super::{
+    cli::{self, RequestArgs},
+    gateway::Format,
+    redmine_service::{reports_client::ReportsClient, ListRequest, ReportResponse, User},
+    REDMINE_API_KEY_METADATA,
+};
+use crate::{secret::Secret, view::document};
+
+/// Env var holding the bearer token sent to the service.
+const TOKEN_ENV: &str = "REPORTS_TOKEN";
+/// Env var naming a file holding the bearer token instead.
+const TOKEN_FILE_ENV: &str = "REPORTS_TOKEN_FILE";
+
+/// Arguments of the `client` subcommand, which asks a running service for reports.
+#[derive(Debug, Default, clap::Args)]
+pub struct ClientArgs {
+    /// Service URL like https://reports:50051, defaults to server.grpc_addr
+    #[arg(long)]
+    pub server: Option<String>,
+    /// PEM certificate of the CA that signed the service certificate
+    #[arg(long)]
+    pub ca_cert: Option<PathBuf>,
+    /// Send the token or API key to an http:// service URL, unencrypted
+    #[arg(long)]
+    pub allow_http: bool,
+    /// File holding your own Redmine API key, passed on to the service
+    #[arg(long)]
+    pub redmine_api_key_file: Option<PathBuf>,
+    /// Redmine user id, login, email or display name, may be repeated
+    #[arg(long = "user")]
+    pub users: Vec<String>,
+    #[command(flatten)]
+    pub request: RequestArgs,
+    /// Seconds the service may take to answer
+    #[arg(long)]
+    pub timeout: Option<u64>,
+    /// Directory to save a file per user in instead of printing the report
+    #[arg(long, short)]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Adds the bearer token and the caller's own Redmine API key to every call.
+#[derive(Clone)]
+struct Credentials {
+    token: Option<MetadataValue<Ascii>>,
+    api_key: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for Credentials {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        if let Some(api_key) = &self.api_key {
+            request
+                .metadata_mut()
+                .insert(REDMINE_API_KEY_METADATA, api_key.clone());
+        }
+        Ok(request)
+    }
+}
+
+type Client = ReportsClient<InterceptedService<Channel, Credentials>>;
+
+/// The bearer token from `REPORTS_TOKEN` or the file named by `REPORTS_TOKEN_FILE`.
+fn token() -> Result<Option<Secret>> {
+    match (std::env::var(TOKEN_ENV), std::env::var_os(TOKEN_FILE_ENV)) {
+        (Ok(_), Some(_)) => bail!(
+            "env {} and {} are mutually exclusive",
+            TOKEN_ENV,
+            TOKEN_FILE_ENV
+        ),
+        (Ok(token), None) => Ok(Some(Secret::new(token.trim().to_string()))),
+        (Err(_), Some(path)) => Ok(Some(Secret::from_file(Path::new(&path))?)),
+        (Err(_), None) => Ok(None),
+    }
+}
+
+async fn connect(server: &str, args: &ClientArgs) -> Result<Client> {
+    let token = match token()? {
+        Some(token) => Some(
+            format!("Bearer {}", token.expose())
+                .parse()
+                .with_context(|| "the bearer token is not a valid header value".to_string())?,
+        ),
+        None => None,
+    };
+    let api_key = match &args.redmine_api_key_file {
+        Some(path) => Some(
+            Secret::from_file(path)?
+                .expose()
+                .parse()
+                .with_context(|| "the Redmine API key is not a valid header value".to_string())?,
+        ),
+        None => None,
+    };
+
+    let mut endpoint = Channel::from_shared(server.to_string())
+        .with_context(|| format!("invalid service URL {}", server))?;
+    if server.starts_with("https://") {
+        let mut tls = ClientTlsConfig::new();
+        if let Some(path) = &args.ca_cert {
+            tls = tls.ca_certificate(Certificate::from_pem(
+                std::fs::read(path)
+                    .with_context(|| format!("unable to read {}", path.display()))?,
+            ));
+        }
+        endpoint = endpoint.tls_config(tls)?;
+    } else if (token.is_some() || api_key.is_some()) && !args.allow_http {
+        bail!(
+            "{} is not an https:// URL, pass --allow-http to send credentials unencrypted",
+            server
+        );
+    }
+    let channel = endpoint
+        .connect()
+        .await
+        .with_context(|| format!("unable to connect to {}", server))?;
+
+    Ok(ReportsClient::with_interceptor(
+        channel,
+        Credentials { token, api_key },
+    ))
+}
+
+/// Turns a `--user` value into a user id, or into a login left for the service to resolve.
+async fn resolve_user(client: &mut Client, user: &str) -> Result<Result<u64, String>> {
+    if let Ok(user_id) = user.parse() {
+        return Ok(Ok(user_id));
+    }
+
+    let users = client
+        .list_users(ListRequest {
+            search: user.to_string(),
+            page_size: 500,
+            ..Default::default()
+        })
+        .await
+        .map_err(|status| anyhow!("{}", status.message()))?
+        .into_inner()
+        .users;
+
+    pick_user(user, &users)
+}
+
+/// The only one of `users` with `user` as login or name, or `user` itself as login or email
+/// when there is none.
+fn pick_user(user: &str, users: &[User]) -> Result<Result<u64, String>> {
+    let found = users
+        .iter()
+        .filter(|found| {
+            found.login.eq_ignore_ascii_case(user) || found.name.eq_ignore_ascii_case(user)
+        })
+        .collect_vec();
+
+    match found.as_slice() {
+        [] => Ok(Err(user.to_string())),
+        [found] => Ok(Ok(found.id)),
+        found => bail!(
+            "user '{}' is ambiguous, use one of the ids {}",
+            user,
+            found.iter().map(|user| user.id).join(", ")
+        ),
+    }
+}
+
+fn save(response: &ReportResponse, format: Format, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("unable to create {}", dir.display()))?;
+
+    for report in &response.reports {
+        let markdown = || {
+            document::markdown([(
+                report.user_id,
+                report.user_name.as_str(),
+                report.report.as_str(),
+            )])
+        };
+        let (extension, content) = match format {
+            Format::Json => ("json", serde_json::to_string_pretty(report)? + "\n"),
+            Format::Markdown => ("md", markdown()),
+            Format::Html => ("html", document::html(&markdown())),
+        };
+
+        let path = dir.join(format!("user-{}.{}", report.user_id, extension));
+        std::fs::write(&path, content)
+            .with_context(|| format!("write {} failed", path.display()))?;
+        info!(
+            "Report of user #{} written to {}",
+            report.user_id,
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Asks the service at `server` for a report and prints or saves it.
+pub async fn report(server: &str, args: ClientArgs) -> Result<()> {
+    let format = args
+        .request
+        .format
+        .as_deref()
+        .and_then(Format::from_preset)
+        .unwrap_or(Format::Markdown);
+    let mut client = connect(server, &args).await?;
+
+    let mut request = args.request.request();
+    for user in &args.users {
+        match resolve_user(&mut client, user).await? {
+            Ok(user_id) => request.user_id.push(user_id),
+            Err(login) => request.login.push(login),
+        }
+    }
+
+    let mut request = Request::new(request);
+    if let Some(timeout) = args.timeout {
+        request.set_timeout(Duration::from_secs(timeout));
+    }
+    let response = client
+        .generate_report(request)
+        .await
+        .map_err(|status| anyhow!("{}", status.message()))?
+        .into_inner();
+
+    match &args.output_dir {
+        Some(dir) => save(&response, format, dir),
+        None => {
+            print!("{}", cli::render(&response, format)?);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::controller::redmine_service::report_response::PerUserReport;
+
+    fn user(id: u64, login: &str, name: &str) -> User {
+        User {
+            id,
+            login: login.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn pick_users() {
+        let users = [
+            user(3, "jdoe", "John Doe"),
+            user(7, "jdoe2", "John Doe"),
+            user(9, "asmith", "Anna Smith"),
+        ];
+
+        assert_eq!(pick_user("JDoe", &users).unwrap(), Ok(3));
+        assert_eq!(pick_user("anna smith", &users).unwrap(), Ok(9));
+        // searches also match emails, which are not listed
+        assert_eq!(
+            pick_user("jdoe@example.com", &users).unwrap(),
+            Err("jdoe@example.com".to_string())
+        );
+        assert_eq!(
+            pick_user("John Doe", &users).unwrap_err().to_string(),
+            "user 'John Doe' is ambiguous, use one of the ids 3, 7"
+        );
+    }
+
+    #[test]
+    fn save_report_per_user() {
+        let dir = std::env::temp_dir().join(format!("reports-{}", std::process::id()));
+        let response = ReportResponse {
+            reports: vec![
+                PerUserReport {
+                    user_id: 3,
+                    user_name: "John Doe".to_string(),
+                    report: "* **#1: Issue 1**\n\n  Reviewed  \n".to_string(),
+                },
+                PerUserReport {
+                    user_id: 9,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        save(&response, Format::Markdown, &dir).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("user-3.md")).unwrap(),
+            "## John Doe (#3)\n\n* **#1: Issue 1**\n\n  Reviewed  \n\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("user-9.md")).unwrap(),
+            "## User #9\n\n\n"
+        );
+
+        save(&response, Format::Json, &dir).unwrap();
+        let saved: PerUserReport =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("user-3.json")).unwrap())
+                .unwrap();
+        assert_eq!(saved, response.reports[0]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuse_http_with_credentials() {
+        // nothing listens on port 1
+        let server = "http://127.0.0.1:1";
+        let err = connect(server, &ClientArgs::default()).await.err().unwrap();
+        assert!(err.to_string().starts_with("unable to connect"));
+
+        let path = std::env::temp_dir().join(format!("client-key-{}", std::process::id()));
+        std::fs::write(&path, "0123456789abcdef\n").unwrap();
+        let args = ClientArgs {
+            redmine_api_key_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let err = connect(server, &args).await.err().unwrap();
+        assert!(err.to_string().contains("--allow-http"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
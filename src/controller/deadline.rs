@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tonic::{metadata::MetadataMap, Status};
+
+const GRPC_TIMEOUT_METADATA: &str = "grpc-timeout";
+/// Time kept back from the client's timeout, so the service answers with its own
+/// `deadline_exceeded` before tonic cancels the call.
+const MAX_MARGIN: Duration = Duration::from_millis(200);
+
+/// Parses a `grpc-timeout` value: up to 8 digits and a unit, e.g. `1500m`.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount = amount.parse::<u64>().ok()?;
+
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Deadline of a call from its `grpc-timeout` metadata, if the client set one.
+pub fn deadline(metadata: &MetadataMap) -> Result<Option<Instant>, Status> {
+    let timeout = match metadata.get(GRPC_TIMEOUT_METADATA) {
+        Some(timeout) => timeout,
+        None => return Ok(None),
+    };
+
+    let timeout = timeout
+        .to_str()
+        .ok()
+        .and_then(parse_timeout)
+        .ok_or_else(|| {
+            Status::invalid_argument(format!("{} is malformed", GRPC_TIMEOUT_METADATA))
+        })?;
+    let margin = (timeout / 10).min(MAX_MARGIN);
+
+    Ok(Some(Instant::now() + timeout - margin))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_grpc_timeout() {
+        assert_eq!(parse_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_timeout("1500m"), Some(Duration::from_millis(1500)));
+        assert_eq!(
+            parse_timeout("99999999n"),
+            Some(Duration::from_nanos(99999999))
+        );
+        assert_eq!(parse_timeout("100000000S"), None);
+        assert_eq!(parse_timeout("10s"), None);
+        assert_eq!(parse_timeout("S"), None);
+        assert_eq!(parse_timeout("-1S"), None);
+    }
+}
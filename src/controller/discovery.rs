@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use tonic::Status;
+
+use super::redmine_service::ListRequest;
+use crate::{
+    metrics,
+    model::{Activity, Project, Redmine, User},
+    view::time_entries::redmine_error,
+};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// A collection with the time it was fetched at.
+type Entry<T> = (Instant, Arc<Vec<T>>);
+
+/// Whole Redmine collections per API key, kept for `ttl`.
+struct Cache<T> {
+    /// Collection name for the metrics.
+    name: &'static str,
+    ttl: Duration,
+    entries: Mutex<HashMap<u64, Entry<T>>>,
+}
+
+impl<T> Cache<T> {
+    fn new(name: &'static str, ttl: Duration) -> Self {
+        Self {
+            name,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn get_or_fetch<F>(&self, redmine: &Redmine, fetch: F) -> Result<Arc<Vec<T>>, Status>
+    where
+        F: Future<Output = anyhow::Result<Vec<T>>>,
+    {
+        let key = redmine.cache_key();
+        if let Some((fetched, items)) = self.entries.lock().unwrap().get(&key) {
+            if fetched.elapsed() < self.ttl {
+                metrics::cache_lookup(self.name, true);
+                return Ok(items.clone());
+            }
+        }
+        metrics::cache_lookup(self.name, false);
+
+        let items = Arc::new(fetch.await.map_err(|err| redmine_error("list", err))?);
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        entries.insert(key, (Instant::now(), items.clone()));
+        debug!("{} Redmine collections cached", entries.len());
+
+        Ok(items)
+    }
+}
+
+/// Cached users, projects and activities behind the list RPCs.
+#[derive(Clone)]
+pub struct Discovery {
+    users: Arc<Cache<User>>,
+    projects: Arc<Cache<Project>>,
+    activities: Arc<Cache<Activity>>,
+}
+
+impl std::fmt::Debug for Discovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Discovery")
+            .field("ttl", &self.users.ttl)
+            .finish()
+    }
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+impl Discovery {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            users: Arc::new(Cache::new("users", ttl)),
+            projects: Arc::new(Cache::new("projects", ttl)),
+            activities: Arc::new(Cache::new("activities", ttl)),
+        }
+    }
+
+    pub async fn users(&self, redmine: &Redmine) -> Result<Arc<Vec<User>>, Status> {
+        self.users.get_or_fetch(redmine, redmine.get_users()).await
+    }
+
+    pub async fn projects(&self, redmine: &Redmine) -> Result<Arc<Vec<Project>>, Status> {
+        self.projects
+            .get_or_fetch(redmine, redmine.get_projects())
+            .await
+    }
+
+    pub async fn activities(&self, redmine: &Redmine) -> Result<Arc<Vec<Activity>>, Status> {
+        self.activities
+            .get_or_fetch(redmine, redmine.get_activities())
+            .await
+    }
+}
+
+/// Case-insensitive substring search over `fields`, an empty search matches everything.
+pub fn matches(search: &str, fields: &[&str]) -> bool {
+    let search = search.trim().to_lowercase();
+
+    search.is_empty()
+        || fields
+            .iter()
+            .any(|field| field.to_lowercase().contains(&search))
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_page_token: String,
+    pub total_size: u32,
+}
+
+/// Cuts the page described by `request` out of all matching items.
+///
+/// Page tokens are plain offsets, good enough while the cached collection stays the same.
+pub fn paginate<T>(items: Vec<T>, request: &ListRequest) -> Result<Page<T>, Status> {
+    let offset = match request.page_token.as_str() {
+        "" => 0,
+        token => token
+            .parse::<usize>()
+            .map_err(|_| Status::invalid_argument("page_token: malformed page token"))?,
+    };
+    let page_size = match request.page_size as usize {
+        0 => DEFAULT_PAGE_SIZE,
+        page_size => page_size.min(MAX_PAGE_SIZE),
+    };
+
+    let total_size = items.len();
+    let end = offset.saturating_add(page_size);
+
+    Ok(Page {
+        items: items.into_iter().skip(offset).take(page_size).collect(),
+        next_page_token: match end < total_size {
+            true => end.to_string(),
+            false => String::new(),
+        },
+        total_size: total_size as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn paginate_items() {
+        let request = |page_size, page_token: &str| ListRequest {
+            page_size,
+            page_token: page_token.to_string(),
+            ..Default::default()
+        };
+
+        let page = paginate((1..=5).collect(), &request(2, "")).unwrap();
+        assert_eq!(
+            (page.items, page.next_page_token.as_str(), page.total_size),
+            (vec![1, 2], "2", 5)
+        );
+        let page = paginate((1..=5).collect(), &request(2, "4")).unwrap();
+        assert_eq!(
+            (page.items, page.next_page_token.as_str(), page.total_size),
+            (vec![5], "", 5)
+        );
+        let page = paginate((1..=5).collect(), &request(0, "")).unwrap();
+        assert_eq!(page.items.len(), 5);
+
+        assert!(paginate(vec![1], &request(2, "x")).is_err());
+
+        assert!(matches(" ALI ", &["alice", "Alice A"]));
+        assert!(!matches("bob", &["alice", "Alice A"]));
+        assert!(matches("", &[]));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Extension},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Headers, Html, IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use log::info;
+use serde_json::json;
+use tonic::{metadata::MetadataMap, service::Interceptor, Code, Status};
+
+use super::{
+    auth::{Authenticator, Caller},
+    redmine_service::{reports_server::Reports, ReportRequest, ReportResponse},
+    ReportService, REQUEST_ID_METADATA,
+};
+use crate::{metrics, view::document};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Format {
+    Json,
+    Markdown,
+    Html,
+}
+
+impl Format {
+    /// Format named by a report preset, see [`super::presets::FORMATS`].
+    pub(super) fn from_preset(format: &str) -> Option<Self> {
+        match format {
+            "json" => Some(Self::Json),
+            "markdown" => Some(Self::Markdown),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the first supported media type of an `Accept` header, if any.
+fn negotiate(accept: Option<&HeaderValue>) -> Option<Format> {
+    accept
+        .and_then(|accept| accept.to_str().ok())
+        .into_iter()
+        .flat_map(|accept| accept.split(','))
+        .find_map(|media_type| match media_type.split(';').next()?.trim() {
+            "application/json" => Some(Format::Json),
+            "text/markdown" => Some(Format::Markdown),
+            "text/html" => Some(Format::Html),
+            _ => None,
+        })
+}
+
+/// HTTP mapping of gRPC codes, following the grpc-gateway conventions.
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::Cancelled | Code::Unknown | Code::Internal | Code::DataLoss => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn error_response(status: Status) -> Response {
+    (
+        http_status(status.code()),
+        Json(json!({
+            "code": status.code() as i32,
+            "message": status.message(),
+        })),
+    )
+        .into_response()
+}
+
+/// Whole markdown document of a report, with a section per user.
+pub(super) fn markdown(response: &ReportResponse) -> String {
+    document::markdown(response.reports.iter().map(|report| {
+        (
+            report.user_id,
+            report.user_name.as_str(),
+            report.report.as_str(),
+        )
+    }))
+}
+
+fn report_response(response: ReportResponse, format: Format) -> Response {
+    let markdown = || markdown(&response);
+
+    match format {
+        Format::Json => Json(&response).into_response(),
+        Format::Markdown => (
+            Headers([(header::CONTENT_TYPE, "text/markdown; charset=utf-8")]),
+            markdown(),
+        )
+            .into_response(),
+        Format::Html => Html(document::html(&markdown())).into_response(),
+    }
+}
+
+/// Peer of an HTTP request, as tonic only knows the peers of gRPC calls.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RemoteAddr(pub SocketAddr);
+
+struct Gateway {
+    service: ReportService,
+    authenticator: Authenticator,
+}
+
+impl Gateway {
+    /// Runs the gRPC authentication on HTTP headers, which double as request metadata.
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Caller, Status> {
+        let mut request = tonic::Request::new(());
+        *request.metadata_mut() = MetadataMap::from_headers(headers.clone());
+
+        self.authenticator
+            .clone()
+            .call(request)?
+            .extensions()
+            .get::<Caller>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("request was not authenticated"))
+    }
+
+    async fn generate_report(
+        &self,
+        peer: SocketAddr,
+        headers: HeaderMap,
+        body: ReportRequest,
+    ) -> Result<tonic::Response<ReportResponse>, Status> {
+        let caller = self.authenticate(&headers)?;
+
+        let mut request = tonic::Request::new(body);
+        *request.metadata_mut() = MetadataMap::from_headers(headers);
+        request.extensions_mut().insert(caller);
+        request.extensions_mut().insert(RemoteAddr(peer));
+
+        // HTTP calls skip the gRPC server and its metrics
+        metrics::rpc("GenerateReport", self.service.generate_report(request)).await
+    }
+}
+
+async fn generate_report(
+    Extension(gateway): Extension<Arc<Gateway>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    info!("Got an HTTP report request from {}", peer);
+
+    let request: ReportRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => {
+            return error_response(Status::invalid_argument(format!(
+                "malformed ReportRequest: {}",
+                err
+            )))
+        }
+    };
+    // the preset format only applies when the client has no preference
+    let format = negotiate(headers.get(header::ACCEPT))
+        .or_else(|| {
+            let preset = gateway.service.presets().get(&request.preset).ok()?;
+            Format::from_preset(&preset.format)
+        })
+        .unwrap_or(Format::Json);
+
+    #[cfg(feature = "trace")]
+    let span = crate::telemetry::http_span("POST /v1/reports", &headers);
+    let response = gateway.generate_report(peer, headers, request);
+    #[cfg(feature = "trace")]
+    let response = tracing::Instrument::instrument(response, span);
+
+    match response.await {
+        Ok(response) => {
+            let request_id = request_id(response.metadata());
+            with_request_id(report_response(response.into_inner(), format), request_id)
+        }
+        Err(status) => {
+            let request_id = request_id(status.metadata());
+            with_request_id(error_response(status), request_id)
+        }
+    }
+}
+
+/// Request id the gRPC service answered with.
+fn request_id(metadata: &MetadataMap) -> Option<HeaderValue> {
+    let request_id = metadata.get(REQUEST_ID_METADATA)?.to_str().ok()?;
+    HeaderValue::from_str(request_id).ok()
+}
+
+fn with_request_id(mut response: Response, request_id: Option<HeaderValue>) -> Response {
+    if let Some(request_id) = request_id {
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_METADATA, request_id);
+    }
+    response
+}
+
+/// HTTP/JSON mapping of the `Reports` service.
+///
+/// `POST /v1/reports` takes a JSON `ReportRequest` and answers with a JSON `ReportResponse`,
+/// or a whole markdown/HTML document depending on the `Accept` header.
+pub fn router(service: ReportService, authenticator: Authenticator) -> Router {
+    Router::new()
+        .route("/v1/reports", post(generate_report))
+        .layer(Extension(Arc::new(Gateway {
+            service,
+            authenticator,
+        })))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn negotiate_format() {
+        let negotiate = |accept: &'static str| negotiate(Some(&HeaderValue::from_static(accept)));
+
+        assert_eq!(super::negotiate(None), None);
+        assert_eq!(negotiate("application/json"), Some(Format::Json));
+        assert_eq!(
+            negotiate("text/markdown; charset=utf-8"),
+            Some(Format::Markdown)
+        );
+        assert_eq!(
+            negotiate("text/html,application/xhtml+xml,*/*;q=0.8"),
+            Some(Format::Html)
+        );
+        assert_eq!(
+            negotiate("image/png, text/markdown"),
+            Some(Format::Markdown)
+        );
+        assert_eq!(negotiate("image/png"), None);
+        assert_eq!(negotiate("*/*"), None);
+    }
+
+    #[test]
+    fn return_request_id() {
+        let mut status = Status::not_found("no such preset");
+        status
+            .metadata_mut()
+            .insert(REQUEST_ID_METADATA, "a1b2c3".parse().unwrap());
+
+        let request_id = request_id(status.metadata());
+        let response = with_request_id(error_response(status), request_id);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["x-request-id"], "a1b2c3");
+
+        let response = with_request_id(error_response(Status::internal("")), None);
+        assert!(!response.headers().contains_key("x-request-id"));
+    }
+}
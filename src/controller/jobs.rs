@@ -0,0 +1,410 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use time::OffsetDateTime;
+use tokio::{sync::Semaphore, task::AbortHandle};
+use tonic::{Code, Status};
+
+use super::{
+    json::datetime_to_timestamp,
+    redmine_service::{ReportJob, ReportJobState, ReportResponse},
+};
+
+pub const DEFAULT_WORKERS: usize = 4;
+pub const DEFAULT_QUEUE: usize = 100;
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+enum Outcome {
+    Pending,
+    Succeeded(ReportResponse),
+    Failed(Code, String),
+    Cancelled,
+}
+
+/// The submitting caller with the Redmine credentials its report is fetched with, the only
+/// one who may see the job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub caller: String,
+    pub credentials: Credentials,
+}
+
+/// The Redmine key of a job, the service key being the same across its rotations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credentials {
+    ServiceKey,
+    /// Hash of the caller's own key.
+    OwnKey(u64),
+}
+
+impl Credentials {
+    pub fn own_key(api_key: &str) -> Self {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        api_key.hash(&mut hasher);
+        Self::OwnKey(hasher.finish())
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    owner: Owner,
+    running: bool,
+    outcome: Outcome,
+    submitted_at: OffsetDateTime,
+    finished_at: Option<OffsetDateTime>,
+    /// Monotonic twin of `finished_at` for the retention.
+    finished: Option<Instant>,
+    task: Option<AbortHandle>,
+}
+
+impl Job {
+    fn state(&self) -> ReportJobState {
+        match (&self.outcome, self.running) {
+            (Outcome::Pending, false) => ReportJobState::Queued,
+            (Outcome::Pending, true) => ReportJobState::Running,
+            (Outcome::Succeeded(_), _) => ReportJobState::Succeeded,
+            (Outcome::Failed(..), _) => ReportJobState::Failed,
+            (Outcome::Cancelled, _) => ReportJobState::Cancelled,
+        }
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        self.outcome = outcome;
+        self.finished_at = Some(OffsetDateTime::now_utc());
+        self.finished = Some(Instant::now());
+        self.task = None;
+    }
+
+    fn to_proto(&self, job_id: &str) -> ReportJob {
+        ReportJob {
+            job_id: job_id.to_string(),
+            state: self.state() as i32,
+            error: match &self.outcome {
+                Outcome::Failed(_, message) => message.clone(),
+                _ => String::new(),
+            },
+            submitted_at: Some(datetime_to_timestamp(self.submitted_at)),
+            finished_at: self.finished_at.map(datetime_to_timestamp),
+        }
+    }
+}
+
+/// Reports generated in the background by a bounded number of workers.
+///
+/// At most `queue` jobs wait for a worker. Finished jobs are kept for `retention`, then
+/// dropped with their results.
+#[derive(Clone)]
+pub struct Jobs {
+    workers: Arc<Semaphore>,
+    queue: usize,
+    retention: Duration,
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl std::fmt::Debug for Jobs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jobs")
+            .field("idle_workers", &self.workers.available_permits())
+            .field("queue", &self.queue)
+            .field("retention", &self.retention)
+            .finish()
+    }
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new(DEFAULT_WORKERS, DEFAULT_QUEUE, DEFAULT_RETENTION)
+    }
+}
+
+impl Jobs {
+    pub fn new(workers: usize, queue: usize, retention: Duration) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(workers)),
+            queue,
+            retention,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Job>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| {
+            job.finished
+                .is_none_or(|finished| finished.elapsed() < self.retention)
+        });
+        jobs
+    }
+
+    /// Queues `report` for the next free worker, fails with `resource_exhausted` when the
+    /// queue is full.
+    pub fn submit<F>(&self, owner: Owner, report: F) -> Result<ReportJob, Status>
+    where
+        F: Future<Output = Result<ReportResponse, Status>> + Send + 'static,
+    {
+        let mut jobs = self.lock();
+        let queued = jobs
+            .values()
+            .filter(|job| job.state() == ReportJobState::Queued)
+            .count();
+        if queued >= self.queue {
+            return Err(Status::resource_exhausted(format!(
+                "{} report jobs are waiting already, try again later",
+                queued
+            )));
+        }
+
+        let job_id = uuid::Uuid::new_v4().to_string();
+        let job = Job {
+            owner,
+            running: false,
+            outcome: Outcome::Pending,
+            submitted_at: OffsetDateTime::now_utc(),
+            finished_at: None,
+            finished: None,
+            task: None,
+        };
+        let reply = job.to_proto(&job_id);
+
+        // registered before the task starts, which updates it
+        jobs.insert(job_id.clone(), job);
+
+        let task = tokio::spawn({
+            let workers = self.workers.clone();
+            let jobs = self.jobs.clone();
+            let job_id = job_id.clone();
+
+            async move {
+                // the semaphore is never closed
+                let _worker = workers.acquire_owned().await.unwrap();
+                if let Some(job) = jobs.lock().unwrap().get_mut(&job_id) {
+                    job.running = true;
+                }
+                info!("Report job {} started", job_id);
+
+                let outcome = match report.await {
+                    Ok(response) => Outcome::Succeeded(response),
+                    Err(status) => {
+                        warn!("Report job {} failed: {}", job_id, status.message());
+                        Outcome::Failed(status.code(), status.message().to_string())
+                    }
+                };
+                match jobs.lock().unwrap().get_mut(&job_id) {
+                    // a cancel may win the race against the end of the report
+                    Some(job) if matches!(job.outcome, Outcome::Pending) => job.finish(outcome),
+                    _ => return,
+                }
+                info!("Report job {} finished", job_id);
+            }
+        });
+        if let Some(job) = jobs.get_mut(&job_id) {
+            job.task = Some(task.abort_handle());
+        }
+
+        // a panicking report would otherwise leave its job running for ever
+        tokio::spawn({
+            let jobs = self.jobs.clone();
+
+            async move {
+                let Err(error) = task.await else {
+                    return;
+                };
+                if let Some(job) = jobs.lock().unwrap().get_mut(&job_id) {
+                    if matches!(job.outcome, Outcome::Pending) {
+                        warn!("Report job {} failed: {}", job_id, error);
+                        job.finish(Outcome::Failed(
+                            Code::Internal,
+                            "the report failed unexpectedly".to_string(),
+                        ));
+                    }
+                }
+            }
+        });
+
+        Ok(reply)
+    }
+
+    /// Unknown jobs and jobs of other callers are both `not_found`.
+    fn with_job<T>(
+        &self,
+        owner: &Owner,
+        job_id: &str,
+        f: impl FnOnce(&mut Job) -> Result<T, Status>,
+    ) -> Result<T, Status> {
+        match self.lock().get_mut(job_id) {
+            Some(job) if job.owner == *owner => f(job),
+            _ => Err(Status::not_found(format!("no report job {}", job_id))),
+        }
+    }
+
+    pub fn status(&self, owner: &Owner, job_id: &str) -> Result<ReportJob, Status> {
+        self.with_job(owner, job_id, |job| Ok(job.to_proto(job_id)))
+    }
+
+    /// Returns the report of a succeeded job, or the error of a failed one.
+    pub fn result(&self, owner: &Owner, job_id: &str) -> Result<ReportResponse, Status> {
+        self.with_job(owner, job_id, |job| match &job.outcome {
+            Outcome::Pending => Err(Status::failed_precondition(format!(
+                "report job {} has not finished yet",
+                job_id
+            ))),
+            Outcome::Succeeded(response) => Ok(response.clone()),
+            Outcome::Failed(code, message) => Err(Status::new(*code, message.clone())),
+            Outcome::Cancelled => Err(Status::cancelled(format!(
+                "report job {} was cancelled",
+                job_id
+            ))),
+        })
+    }
+
+    pub fn cancel(&self, owner: &Owner, job_id: &str) -> Result<ReportJob, Status> {
+        self.with_job(owner, job_id, |job| {
+            if !matches!(job.outcome, Outcome::Pending) {
+                return Err(Status::failed_precondition(format!(
+                    "report job {} has already finished",
+                    job_id
+                )));
+            }
+
+            if let Some(task) = &job.task {
+                task.abort();
+            }
+            job.finish(Outcome::Cancelled);
+            info!("Report job {} cancelled", job_id);
+
+            Ok(job.to_proto(job_id))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn owner(caller: &str, api_key: &str) -> Owner {
+        Owner {
+            caller: caller.to_string(),
+            credentials: Credentials::own_key(api_key),
+        }
+    }
+
+    fn state(jobs: &Jobs, job_id: &str) -> ReportJobState {
+        ReportJobState::from_i32(jobs.status(&owner("owner", "key"), job_id).unwrap().state)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn run_jobs() {
+        let jobs = Jobs::new(1, 1, Duration::from_secs(60));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+
+        let first = jobs
+            .submit(owner("owner", "key"), async move {
+                released.await.unwrap();
+                Ok(ReportResponse::default())
+            })
+            .unwrap()
+            .job_id;
+        tokio::task::yield_now().await;
+        let second = jobs
+            .submit(owner("owner", "key"), async {
+                Err(Status::invalid_argument("bad"))
+            })
+            .unwrap()
+            .job_id;
+        assert_eq!(
+            jobs.submit(owner("owner", "key"), futures::future::pending())
+                .unwrap_err()
+                .code(),
+            Code::ResourceExhausted
+        );
+
+        assert_eq!(state(&jobs, &first), ReportJobState::Running);
+        assert_eq!(state(&jobs, &second), ReportJobState::Queued);
+        assert_eq!(
+            jobs.result(&owner("owner", "key"), &first)
+                .unwrap_err()
+                .code(),
+            Code::FailedPrecondition
+        );
+        assert_eq!(
+            jobs.status(&owner("other", "key"), &first)
+                .unwrap_err()
+                .code(),
+            Code::NotFound
+        );
+        assert_eq!(
+            jobs.status(&owner("owner", "other key"), &first)
+                .unwrap_err()
+                .code(),
+            Code::NotFound
+        );
+
+        release.send(()).unwrap();
+        while state(&jobs, &second) != ReportJobState::Failed {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            jobs.result(&owner("owner", "key"), &first).unwrap(),
+            ReportResponse::default()
+        );
+        assert_eq!(
+            jobs.result(&owner("owner", "key"), &second)
+                .unwrap_err()
+                .code(),
+            Code::InvalidArgument
+        );
+        assert!(jobs.cancel(&owner("owner", "key"), &first).is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_and_expire_jobs() {
+        let jobs = Jobs::new(1, 1, Duration::ZERO);
+
+        let job_id = jobs
+            .submit(owner("owner", "key"), futures::future::pending())
+            .unwrap()
+            .job_id;
+        tokio::task::yield_now().await;
+
+        assert_eq!(
+            ReportJobState::from_i32(jobs.cancel(&owner("owner", "key"), &job_id).unwrap().state),
+            Some(ReportJobState::Cancelled)
+        );
+        assert_eq!(
+            jobs.status(&owner("owner", "key"), &job_id)
+                .unwrap_err()
+                .code(),
+            Code::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_panicking_jobs() {
+        let jobs = Jobs::new(1, 1, Duration::from_secs(60));
+
+        let job_id = jobs
+            .submit(owner("owner", "key"), async { panic!("bug") })
+            .unwrap()
+            .job_id;
+        while state(&jobs, &job_id) != ReportJobState::Failed {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            jobs.result(&owner("owner", "key"), &job_id)
+                .unwrap_err()
+                .code(),
+            Code::Internal
+        );
+    }
+}
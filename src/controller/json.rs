@@ -0,0 +1,51 @@
+//! JSON mapping of protobuf well-known types, used by the HTTP gateway.
+
+use prost_types::Timestamp;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+pub fn timestamp_to_datetime(timestamp: &Timestamp) -> Result<OffsetDateTime, String> {
+    let nanos = i128::from(timestamp.seconds) * 1_000_000_000 + i128::from(timestamp.nanos);
+
+    OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|err| err.to_string())
+}
+
+pub fn datetime_to_timestamp(datetime: OffsetDateTime) -> Timestamp {
+    Timestamp {
+        seconds: datetime.unix_timestamp(),
+        nanos: datetime.nanosecond() as i32,
+    }
+}
+
+/// `google.protobuf.Timestamp` as an RFC 3339 string, as in the proto3 JSON mapping.
+pub mod optional_timestamp {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use super::*;
+
+    pub fn serialize<S>(timestamp: &Option<Timestamp>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match timestamp {
+            Some(timestamp) => serializer.serialize_str(
+                &timestamp_to_datetime(timestamp)
+                    .and_then(|datetime| datetime.format(&Rfc3339).map_err(|err| err.to_string()))
+                    .map_err(serde::ser::Error::custom)?,
+            ),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|timestamp| {
+                OffsetDateTime::parse(&timestamp, &Rfc3339)
+                    .map(datetime_to_timestamp)
+                    .map_err(D::Error::custom)
+            })
+            .transpose()
+    }
+}
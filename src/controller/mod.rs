@@ -1,60 +1,888 @@
-use log::info;
+use std::{collections::HashMap, net::SocketAddr};
+
+use itertools::Itertools;
+use log::{debug, info};
 use redmine_service::reports_server::Reports;
-use reqwest::Url;
-use time::{macros::format_description, Date};
-use tonic::{Request, Response, Status};
+use tonic::{
+    metadata::{Ascii, MetadataMap, MetadataValue},
+    Request, Response, Status,
+};
 #[cfg(feature = "trace")]
 use tracing::instrument;
 
-use self::redmine_service::{report_response::PerUserReport, ReportRequest, ReportResponse};
+use self::{
+    audit::AuditLog,
+    auth::{AllowedUsers, Caller},
+    discovery::Discovery,
+    jobs::{Credentials, Jobs, Owner},
+    period::Calendar,
+    presets::Presets,
+    redmine_service::{
+        report_response::PerUserReport, Activity, AuditLogRequest, AuditLogResponse,
+        DeletePresetRequest, ListActivitiesResponse, ListPresetsResponse, ListProjectsResponse,
+        ListRequest, ListUsersResponse, Project, ReportJob, ReportJobRequest, ReportPreset,
+        ReportRequest, ReportResponse, User,
+    },
+    validation::{Limits, ValidRequest},
+};
+use crate::{
+    logging::{self, Redacted},
+    metrics,
+};
+
+pub mod audit;
+pub mod auth;
+pub mod cli;
+pub mod client;
+pub mod deadline;
+pub mod discovery;
+pub mod gateway;
+pub mod jobs;
+pub mod json;
+pub mod period;
+pub mod presets;
+pub mod validation;
+
+/// Metadata carrying the caller's own Redmine API key.
+const REDMINE_API_KEY_METADATA: &str = "x-redmine-api-key";
+/// Metadata correlating the log lines of a request, echoed in the response.
+const REQUEST_ID_METADATA: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ReportService {
     redmine: crate::model::Redmine,
+    service_key_fallback: bool,
+    limits: Limits,
+    calendar: Calendar,
+    presets: Presets,
+    discovery: Discovery,
+    jobs: Jobs,
+    audit: AuditLog,
 }
 
 pub mod redmine_service {
     tonic::include_proto!("redmine_api");
 }
 
+pub mod google {
+    pub mod r#type {
+        tonic::include_proto!("google.r#type");
+    }
+}
+
 impl ReportService {
-    pub fn new(site: Url, api_key: String) -> Self {
+    /// `service_key_fallback` allows requests without their own Redmine key to use the
+    /// service key of `redmine`.
+    pub fn new(
+        redmine: crate::model::Redmine,
+        service_key_fallback: bool,
+        limits: Limits,
+        calendar: Calendar,
+        presets: Presets,
+        discovery: Discovery,
+        jobs: Jobs,
+    ) -> Self {
         Self {
-            redmine: crate::model::Redmine::new(site, api_key),
+            redmine,
+            service_key_fallback,
+            limits,
+            calendar,
+            presets,
+            discovery,
+            jobs,
+            audit: AuditLog::default(),
         }
     }
-}
 
-#[tonic::async_trait]
-impl Reports for ReportService {
-    #[cfg_attr(feature = "trace", instrument)]
-    async fn generate_report(
+    /// Records report requests in `audit` instead of in memory.
+    pub fn with_audit_log(self, audit: AuditLog) -> Self {
+        Self { audit, ..self }
+    }
+
+    pub fn presets(&self) -> &Presets {
+        &self.presets
+    }
+
+    fn redmine_for(&self, metadata: &MetadataMap) -> Result<crate::model::Redmine, Status> {
+        self.credentials(metadata).map(|(redmine, _)| redmine)
+    }
+
+    fn credentials(
+        &self,
+        metadata: &MetadataMap,
+    ) -> Result<(crate::model::Redmine, Credentials), Status> {
+        match metadata.get(REDMINE_API_KEY_METADATA) {
+            Some(api_key) => {
+                let api_key = api_key.to_str().map_err(|_| {
+                    Status::invalid_argument(format!(
+                        "{} must be a valid ascii string",
+                        REDMINE_API_KEY_METADATA
+                    ))
+                })?;
+
+                debug!("Use the caller's Redmine API key {}", Redacted(api_key));
+                Ok((
+                    self.redmine.with_api_key(api_key.to_string()),
+                    Credentials::own_key(api_key),
+                ))
+            }
+            None if self.service_key_fallback && self.redmine.has_api_key() => {
+                Ok((self.redmine.clone(), Credentials::ServiceKey))
+            }
+            None => Err(Status::unauthenticated(format!(
+                "{} metadata is required",
+                REDMINE_API_KEY_METADATA
+            ))),
+        }
+    }
+
+    /// Report jobs are only handed to the caller that submitted them with the same Redmine
+    /// credentials.
+    fn job_owner<T>(&self, request: &Request<T>) -> Result<Owner, Status> {
+        Ok(Owner {
+            caller: caller(request)?.name,
+            credentials: self.credentials(request.metadata())?.1,
+        })
+    }
+
+    /// Checks a report request before anything is fetched from Redmine, except for the
+    /// requested users the caller has to check.
+    fn prepare_report(
         &self,
         request: Request<ReportRequest>,
-    ) -> Result<Response<ReportResponse>, Status> {
-        use crate::view::time_entries::aggregate_report;
+    ) -> Result<(Caller, crate::model::Redmine, ValidRequest), Status> {
+        let caller = caller(&request)?;
 
-        info!("Got a request from {:?}", request.remote_addr());
+        let redmine = self.redmine_for(request.metadata())?;
 
-        let request = request.into_inner();
-        let format = format_description!("[year]-[month]-[day]");
-        let from = Date::parse(&request.generate_from_ts, &format)
-            .map_err(|_| Status::invalid_argument("generate_from_ts"))?;
-        let to = Date::parse(&request.generate_to_ts, &format)
-            .map_err(|_| Status::invalid_argument("generate_to_ts"))?;
+        let request = match request.into_inner() {
+            request if request.preset.is_empty() => request,
+            request => {
+                let preset = self.presets.get(&request.preset)?;
+                presets::apply(&preset.request.unwrap_or_default(), request)
+            }
+        };
+        let request = validation::validate(request, &self.limits, &self.calendar)?;
+
+        Ok((caller, redmine, request))
+    }
+
+    async fn run_report(
+        &self,
+        caller: &Caller,
+        redmine: &crate::model::Redmine,
+        request: ValidRequest,
+    ) -> Result<ReportResponse, Status> {
+        use crate::view::time_entries::{aggregate_report, resolve_users};
+
+        let _in_flight = metrics::ReportInFlight::start();
+
+        let users = if request.group_ids.is_empty() && request.logins.is_empty() {
+            let user_ids = match &caller.allowed_users {
+                // a saved query alone stands for everyone in its results, a restricted caller
+                // only gets the users it is allowed to see
+                AllowedUsers::Only(allowed) if request.user_ids.is_empty() => {
+                    validation::check_user_count(allowed.len(), &self.limits)?;
+                    allowed.iter().copied().sorted().collect_vec()
+                }
+                _ => request.user_ids.clone(),
+            };
+            user_ids
+                .into_iter()
+                .map(|user_id| (user_id, None))
+                .collect()
+        } else {
+            let users = resolve_users(
+                redmine,
+                &request.user_ids,
+                &request.group_ids,
+                &request.logins,
+            )
+            .await?;
+            if users.is_empty() {
+                // empty groups must not widen a saved query to all of its users
+                return Ok(ReportResponse::default());
+            }
+            validation::check_user_count(users.len(), &self.limits)?;
+            caller.check_users(&users.iter().map(|(user_id, _)| *user_id).collect_vec())?;
+            users
+        };
+        let user_ids = users.iter().map(|(user_id, _)| *user_id).collect_vec();
+        let mut user_names: HashMap<u64, String> = users
+            .into_iter()
+            .filter_map(|(user_id, name)| Some((user_id, name?)))
+            .collect();
 
-        let reports = aggregate_report(&self.redmine, &request.user_id, from, to).await?;
+        let reports =
+            aggregate_report(redmine, &user_ids, &request.filter, request.impersonate).await?;
+        if user_ids.is_empty() {
+            // users derived from a saved query are only known now
+            validation::check_user_count(reports.len(), &self.limits)?;
+            caller.check_users(&reports.iter().map(|report| report.user_id).collect_vec())?;
+        }
 
         let reply = ReportResponse {
             reports: reports
                 .into_iter()
                 .map(|report| PerUserReport {
+                    user_name: match report.user_name.is_empty() {
+                        true => user_names.remove(&report.user_id).unwrap_or_default(),
+                        false => report.user_name,
+                    },
                     user_id: report.user_id,
                     report: report.report,
                 })
                 .collect(),
         };
 
-        Ok(Response::new(reply))
+        Ok(reply)
+    }
+}
+
+/// Request id given by the caller, or a new one if it gave none or an unusable one.
+fn request_id(metadata: &MetadataMap) -> String {
+    metadata
+        .get(REQUEST_ID_METADATA)
+        .and_then(|request_id| request_id.to_str().ok())
+        .filter(|request_id| !request_id.is_empty() && request_id.len() <= MAX_REQUEST_ID_LEN)
+        .map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string)
+}
+
+/// Returns the request id to the caller, errors included.
+fn with_request_id<T>(
+    result: Result<Response<T>, Status>,
+    request_id: &str,
+) -> Result<Response<T>, Status> {
+    let value = match request_id.parse::<MetadataValue<Ascii>>() {
+        Ok(value) => value,
+        Err(_) => return result,
+    };
+
+    match result {
+        Ok(mut response) => {
+            response.metadata_mut().insert(REQUEST_ID_METADATA, value);
+            Ok(response)
+        }
+        Err(mut status) => {
+            status.metadata_mut().insert(REQUEST_ID_METADATA, value);
+            Err(status)
+        }
+    }
+}
+
+/// Peer of a gRPC call, or of the HTTP request the gateway turned into one.
+fn remote_addr<T>(request: &Request<T>) -> Option<SocketAddr> {
+    request.remote_addr().or_else(|| {
+        request
+            .extensions()
+            .get::<gateway::RemoteAddr>()
+            .map(|addr| addr.0)
+    })
+}
+
+fn caller<T>(request: &Request<T>) -> Result<Caller, Status> {
+    request
+        .extensions()
+        .get::<Caller>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("request was not authenticated"))
+}
+
+#[tonic::async_trait]
+impl Reports for ReportService {
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn generate_report(
+        &self,
+        request: Request<ReportRequest>,
+    ) -> Result<Response<ReportResponse>, Status> {
+        let request_id = request_id(request.metadata());
+        let mut entry = audit::entry("GenerateReport", &request, &request_id);
+        audit::requested(&mut entry, request.get_ref());
+
+        let result = logging::with_request_id(request_id.clone(), {
+            let entry = &mut entry;
+
+            async move {
+                info!("Got a request from {:?}", remote_addr(&request));
+
+                // a disconnecting client drops this future, and with it pending Redmine calls
+                let deadline = deadline::deadline(request.metadata())?;
+                let (caller, redmine, request) = self.prepare_report(request)?;
+                audit::validated(entry, &request);
+                caller.check_users(&request.user_ids)?;
+                let redmine = match deadline {
+                    Some(deadline) => redmine.with_deadline(deadline),
+                    None => redmine,
+                };
+                let reply = self.run_report(&caller, &redmine, request).await?;
+
+                Ok(Response::new(reply))
+            }
+        })
+        .await;
+
+        let entry = audit::finished(entry, &result, audit::reported_users);
+        let result = self.audit.record(entry, result).await;
+
+        with_request_id(result, &request_id)
+    }
+
+    /// Validates the request right away, only fetching the report is left to the job.
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn submit_report(
+        &self,
+        request: Request<ReportRequest>,
+    ) -> Result<Response<ReportJob>, Status> {
+        let request_id = request_id(request.metadata());
+        let mut entry = audit::entry("SubmitReport", &request, &request_id);
+        audit::requested(&mut entry, request.get_ref());
+
+        let result = logging::with_request_id(request_id.clone(), {
+            let entry = &mut entry;
+
+            async move {
+                info!("Got a report job from {:?}", remote_addr(&request));
+
+                let owner = self.job_owner(&request)?;
+                let (caller, redmine, request) = self.prepare_report(request)?;
+                audit::validated(entry, &request);
+                caller.check_users(&request.user_ids)?;
+                let service = self.clone();
+                let job = self.jobs.submit(owner, async move {
+                    service.run_report(&caller, &redmine, request).await
+                })?;
+                info!("Submitted report job {}", job.job_id);
+
+                Ok(Response::new(job))
+            }
+        })
+        .await;
+
+        if let Ok(job) = &result {
+            entry.job_id = job.get_ref().job_id.clone();
+        }
+        // the reports are recorded once the result is read
+        let entry = audit::finished(entry, &result, |_| Vec::new());
+        let result = self.audit.record(entry, result).await;
+
+        with_request_id(result, &request_id)
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(
+            skip(self, request),
+            fields(remote_addr = ?remote_addr(&request), job_id = %request.get_ref().job_id)
+        )
+    )]
+    async fn get_report_status(
+        &self,
+        request: Request<ReportJobRequest>,
+    ) -> Result<Response<ReportJob>, Status> {
+        let owner = self.job_owner(&request)?;
+
+        Ok(Response::new(
+            self.jobs.status(&owner, &request.get_ref().job_id)?,
+        ))
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(
+            skip(self, request),
+            fields(remote_addr = ?remote_addr(&request), job_id = %request.get_ref().job_id)
+        )
+    )]
+    async fn get_report_result(
+        &self,
+        request: Request<ReportJobRequest>,
+    ) -> Result<Response<ReportResponse>, Status> {
+        let request_id = request_id(request.metadata());
+        let mut entry = audit::entry("GetReportResult", &request, &request_id);
+        entry.job_id = request.get_ref().job_id.clone();
+
+        let result = self
+            .job_owner(&request)
+            .and_then(|owner| self.jobs.result(&owner, &request.get_ref().job_id))
+            .map(Response::new);
+
+        let entry = audit::finished(entry, &result, audit::reported_users);
+        let result = self.audit.record(entry, result).await;
+
+        with_request_id(result, &request_id)
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(
+            skip(self, request),
+            fields(remote_addr = ?remote_addr(&request), job_id = %request.get_ref().job_id)
+        )
+    )]
+    async fn cancel_report(
+        &self,
+        request: Request<ReportJobRequest>,
+    ) -> Result<Response<ReportJob>, Status> {
+        let owner = self.job_owner(&request)?;
+
+        Ok(Response::new(
+            self.jobs.cancel(&owner, &request.get_ref().job_id)?,
+        ))
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn query_audit_log(
+        &self,
+        request: Request<AuditLogRequest>,
+    ) -> Result<Response<AuditLogResponse>, Status> {
+        caller(&request)?.check_unrestricted("query the audit log")?;
+        let request = request.into_inner();
+
+        let page = discovery::paginate(
+            self.audit.query(&request).await?,
+            &ListRequest {
+                page_size: request.page_size,
+                page_token: request.page_token.clone(),
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Response::new(AuditLogResponse {
+            entries: page.items,
+            next_page_token: page.next_page_token,
+            total_size: page.total_size,
+        }))
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, _request), fields(remote_addr = ?remote_addr(&_request)))
+    )]
+    async fn list_presets(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ListPresetsResponse>, Status> {
+        Ok(Response::new(ListPresetsResponse {
+            presets: self.presets.list(),
+        }))
+    }
+
+    /// Presets are validated like report requests, so they can be used on their own.
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn put_preset(
+        &self,
+        request: Request<ReportPreset>,
+    ) -> Result<Response<ReportPreset>, Status> {
+        caller(&request)?.check_unrestricted("manage report presets")?;
+
+        let preset = request.into_inner();
+        if preset.name.trim().is_empty() {
+            return Err(Status::invalid_argument("name: must not be empty"));
+        }
+        if !preset.format.is_empty() && !presets::FORMATS.contains(&preset.format.as_str()) {
+            return Err(Status::invalid_argument(format!(
+                "format: unknown format '{}', expected one of {}",
+                preset.format,
+                presets::FORMATS.join(", ")
+            )));
+        }
+        let request = preset.request.clone().unwrap_or_default();
+        if !request.preset.is_empty() {
+            return Err(Status::invalid_argument(
+                "request.preset: presets must not refer to other presets",
+            ));
+        }
+        validation::validate(request, &self.limits, &self.calendar)?;
+
+        info!("Put report preset '{}'", preset.name);
+        self.presets.put(preset.clone())?;
+
+        Ok(Response::new(preset))
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn delete_preset(
+        &self,
+        request: Request<DeletePresetRequest>,
+    ) -> Result<Response<()>, Status> {
+        caller(&request)?.check_unrestricted("manage report presets")?;
+
+        let name = request.into_inner().name;
+        info!("Delete report preset '{}'", name);
+        self.presets.delete(&name)?;
+
+        Ok(Response::new(()))
+    }
+
+    /// Restricted callers only see the users they may request reports for.
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn list_users(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let caller = caller(&request)?;
+        let redmine = self.redmine_for(request.metadata())?;
+        let request = request.into_inner();
+
+        let users = self.discovery.users(&redmine).await?;
+        let page = discovery::paginate(
+            users
+                .iter()
+                .filter(|user| caller.allows(user.id))
+                .map(|user| User {
+                    id: user.id,
+                    login: user.login.clone(),
+                    name: user.name(),
+                })
+                .filter(|user| discovery::matches(&request.search, &[&user.login, &user.name]))
+                .collect(),
+            &request,
+        )?;
+
+        Ok(Response::new(ListUsersResponse {
+            users: page.items,
+            next_page_token: page.next_page_token,
+            total_size: page.total_size,
+        }))
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn list_projects(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<ListProjectsResponse>, Status> {
+        caller(&request)?;
+        let redmine = self.redmine_for(request.metadata())?;
+        let request = request.into_inner();
+
+        let projects = self.discovery.projects(&redmine).await?;
+        let page = discovery::paginate(
+            projects
+                .iter()
+                .filter(|project| {
+                    discovery::matches(&request.search, &[&project.name, &project.identifier])
+                })
+                .map(|project| Project {
+                    id: project.id,
+                    name: project.name.clone(),
+                    identifier: project.identifier.clone(),
+                    parent_id: project.parent.as_ref().map_or(0, |parent| parent.id),
+                })
+                .collect(),
+            &request,
+        )?;
+
+        Ok(Response::new(ListProjectsResponse {
+            projects: page.items,
+            next_page_token: page.next_page_token,
+            total_size: page.total_size,
+        }))
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        instrument(skip(self, request), fields(remote_addr = ?remote_addr(&request)))
+    )]
+    async fn list_activities(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<ListActivitiesResponse>, Status> {
+        caller(&request)?;
+        let redmine = self.redmine_for(request.metadata())?;
+        let request = request.into_inner();
+
+        let activities = self.discovery.activities(&redmine).await?;
+        let page = discovery::paginate(
+            activities
+                .iter()
+                .filter(|activity| discovery::matches(&request.search, &[&activity.name]))
+                .map(|activity| Activity {
+                    id: activity.id,
+                    name: activity.name.clone(),
+                    active: activity.active,
+                })
+                .collect(),
+            &request,
+        )?;
+
+        Ok(Response::new(ListActivitiesResponse {
+            activities: page.items,
+            next_page_token: page.next_page_token,
+            total_size: page.total_size,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tonic::Code;
+
+    use super::{redmine_service::ReportJobState, *};
+    use crate::model::mock::MockRedmine;
+
+    fn service(redmine: crate::model::Redmine, service_key_fallback: bool) -> ReportService {
+        ReportService::new(
+            redmine,
+            service_key_fallback,
+            Limits::default(),
+            Calendar::default(),
+            Presets::default(),
+            Discovery::default(),
+            Jobs::default(),
+        )
+    }
+
+    fn with_key(api_key: &str) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(REDMINE_API_KEY_METADATA, api_key.parse().unwrap());
+        metadata
+    }
+
+    #[tokio::test]
+    async fn forward_caller_key() {
+        let mock = MockRedmine::start(|_| (StatusCode::OK, json!({"users": [], "total_count": 0})));
+        let service = service(mock.redmine(Some("service")), true);
+
+        let redmine = service.redmine_for(&with_key("own")).unwrap();
+        assert_eq!(redmine.cache_key(), mock.redmine(Some("own")).cache_key());
+        redmine.get_users().await.unwrap();
+        service
+            .redmine_for(&MetadataMap::new())
+            .unwrap()
+            .get_users()
+            .await
+            .unwrap();
+
+        let api_keys = mock
+            .requests()
+            .into_iter()
+            .map(|request| request.api_key)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            api_keys,
+            vec![Some("own".to_string()), Some("service".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn require_caller_key_without_fallback() {
+        let mock = MockRedmine::start(|_| (StatusCode::OK, json!({})));
+
+        let code = |service: ReportService| {
+            service
+                .redmine_for(&MetadataMap::new())
+                .map(|_| ())
+                .unwrap_err()
+                .code()
+        };
+        assert_eq!(
+            code(service(mock.redmine(Some("service")), false)),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            code(service(mock.redmine(None), true)),
+            Code::Unauthenticated
+        );
+        assert!(service(mock.redmine(None), false)
+            .redmine_for(&with_key("own"))
+            .is_ok());
+    }
+
+    fn saved_query(request: &crate::model::mock::Request) -> (StatusCode, serde_json::Value) {
+        let time_entry = |id, user_id| {
+            json!({
+                "id": id,
+                "hours": 1.0,
+                "comments": "Reviewed",
+                "user": {"id": user_id, "name": format!("User {}", user_id)},
+                "issue": {"id": 1},
+                "project": {"id": 1},
+                "activity": {"id": 9},
+                "spent_on": "2021-01-04",
+            })
+        };
+
+        match request.path.as_str() {
+            "time_entries.json" => (
+                StatusCode::OK,
+                json!({"time_entries": [time_entry(1, 3), time_entry(2, 9)], "total_count": 2}),
+            ),
+            "issues.json" => (
+                StatusCode::OK,
+                json!({"issues": [{"id": 1, "subject": "Issue 1", "tracker": {"id": 1}}]}),
+            ),
+            "groups/4.json" => (StatusCode::OK, json!({"group": {"id": 4, "users": []}})),
+            _ => (StatusCode::NOT_FOUND, json!({})),
+        }
+    }
+
+    fn report_request(
+        allowed_users: AllowedUsers,
+        report: ReportRequest,
+    ) -> Request<ReportRequest> {
+        let mut request = Request::new(ReportRequest {
+            query_id: 5,
+            generate_from_ts: "2021-01-04".to_string(),
+            generate_to_ts: "2021-01-08".to_string(),
+            ..report
+        });
+        request.extensions_mut().insert(Caller {
+            name: "ci".to_string(),
+            allowed_users,
+        });
+        request
+    }
+
+    fn reported_users(response: Response<ReportResponse>) -> Vec<u64> {
+        response
+            .into_inner()
+            .reports
+            .iter()
+            .map(|report| report.user_id)
+            .sorted()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn limit_users_of_saved_query() {
+        let mock = MockRedmine::start(saved_query);
+        let mut service = service(mock.redmine(Some("service")), true);
+
+        let response = service
+            .generate_report(report_request(
+                AllowedUsers::Only([3].into_iter().collect()),
+                ReportRequest::default(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(reported_users(response), vec![3]);
+
+        let response = service
+            .generate_report(report_request(AllowedUsers::Any, ReportRequest::default()))
+            .await
+            .unwrap();
+        assert_eq!(reported_users(response), vec![3, 9]);
+
+        service.limits.max_users = 1;
+        let status = service
+            .generate_report(report_request(AllowedUsers::Any, ReportRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn empty_group_reports_nobody() {
+        let mock = MockRedmine::start(saved_query);
+        let service = service(mock.redmine(Some("service")), true);
+
+        let response = service
+            .generate_report(report_request(
+                AllowedUsers::Any,
+                ReportRequest {
+                    group_id: vec![4],
+                    ..Default::default()
+                },
+            ))
+            .await
+            .unwrap();
+        assert_eq!(reported_users(response), Vec::<u64>::new());
+        assert!(mock
+            .requests()
+            .iter()
+            .all(|request| !request.path.starts_with("time_entries")));
+    }
+
+    #[tokio::test]
+    async fn audit_report_jobs() {
+        let mock = MockRedmine::start(saved_query);
+        let service = service(mock.redmine(Some("service")), true);
+        let job_request = |job_id: &str| {
+            let mut request = Request::new(ReportJobRequest {
+                job_id: job_id.to_string(),
+            });
+            request.extensions_mut().insert(Caller {
+                name: "ci".to_string(),
+                allowed_users: AllowedUsers::Any,
+            });
+            request
+        };
+
+        let job = service
+            .submit_report(report_request(AllowedUsers::Any, ReportRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        loop {
+            let status = service.get_report_status(job_request(&job.job_id)).await;
+            match ReportJobState::from_i32(status.unwrap().get_ref().state) {
+                Some(ReportJobState::Succeeded) => break,
+                Some(ReportJobState::Queued | ReportJobState::Running) => {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await
+                }
+                state => panic!("job ended {:?}", state),
+            }
+        }
+        let response = service
+            .get_report_result(job_request(&job.job_id))
+            .await
+            .unwrap();
+        assert_eq!(reported_users(response), vec![3, 9]);
+        let status = service
+            .get_report_result(job_request("unknown"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let entries = service
+            .audit
+            .query(&AuditLogRequest::default())
+            .await
+            .unwrap();
+        let summary = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.rpc.as_str(),
+                    entry.job_id.as_str(),
+                    entry.query_id,
+                    entry.reported_user_id.iter().copied().sorted().collect(),
+                    entry.outcome.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("SubmitReport", job.job_id.as_str(), 5, vec![], "Ok"),
+                ("GetReportResult", job.job_id.as_str(), 0, vec![3, 9], "Ok"),
+                ("GetReportResult", "unknown", 0, vec![], "NotFound"),
+            ]
+        );
     }
 }
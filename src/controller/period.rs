@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+use time::{Date, Duration, Month, OffsetDateTime, Weekday};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+const PRESETS: &str =
+    "today, yesterday, this_week, last_week, this_month, last_month, this_quarter, last_quarter, \
+     year_to_date";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeekStart {
+    Monday,
+    Sunday,
+}
+
+impl FromStr for WeekStart {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "monday" => Ok(Self::Monday),
+            "sunday" => Ok(Self::Sunday),
+            _ => bail!("unknown week start '{}', expected monday or sunday", s),
+        }
+    }
+}
+
+/// Resolves named periods like `last_week` or `2026-W41` to date ranges.
+#[derive(Debug, Clone, Copy)]
+pub struct Calendar {
+    /// Time zone deciding what "today" is.
+    pub time_zone: &'static Tz,
+    /// First day of `this_week` and `last_week`, ISO weeks always start on Monday.
+    pub week_start: WeekStart,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self {
+            time_zone: time_tz::timezones::db::UTC,
+            week_start: WeekStart::Monday,
+        }
+    }
+}
+
+fn month_range(year: i32, month: Month) -> (Date, Date) {
+    let first = Date::from_calendar_date(year, month, 1).unwrap();
+    let next = match month {
+        Month::December => Date::from_calendar_date(year + 1, Month::January, 1),
+        month => Date::from_calendar_date(year, month.next(), 1),
+    };
+    let last = next.unwrap().previous_day().unwrap();
+
+    (first, last)
+}
+
+fn quarter_range(year: i32, quarter: u8) -> (Date, Date) {
+    let first_month = Month::try_from(quarter * 3 - 2).unwrap();
+
+    (
+        month_range(year, first_month).0,
+        month_range(year, first_month.next().next()).1,
+    )
+}
+
+/// Parses ISO week notation, e.g. `2026-W41`.
+fn parse_iso_week(period: &str) -> Option<(Date, Date)> {
+    let (year, week) = period.split_once("-W")?;
+    if week.len() != 2 {
+        return None;
+    }
+
+    let monday =
+        Date::from_iso_week_date(year.parse().ok()?, week.parse().ok()?, Weekday::Monday).ok()?;
+
+    Some((monday, monday + Duration::days(6)))
+}
+
+impl Calendar {
+    pub fn today(&self) -> Date {
+        self.local_date(OffsetDateTime::now_utc())
+    }
+
+    /// Calendar date of `datetime` in the configured time zone.
+    pub fn local_date(&self, datetime: OffsetDateTime) -> Date {
+        datetime.to_timezone(self.time_zone).date()
+    }
+
+    fn week_range(&self, day: Date) -> (Date, Date) {
+        let offset = match self.week_start {
+            WeekStart::Monday => day.weekday().number_days_from_monday(),
+            WeekStart::Sunday => day.weekday().number_days_from_sunday(),
+        };
+        let first = day - Duration::days(offset.into());
+
+        (first, first + Duration::days(6))
+    }
+
+    /// Returns the first and last day of `period` relative to `today`.
+    pub fn resolve(&self, period: &str, today: Date) -> Result<(Date, Date), String> {
+        let quarter = (u8::from(today.month()) - 1) / 3 + 1;
+
+        let range = match period {
+            "today" => (today, today),
+            "yesterday" => (today.previous_day().unwrap(), today.previous_day().unwrap()),
+            "this_week" => self.week_range(today),
+            "last_week" => self.week_range(today - Duration::weeks(1)),
+            "this_month" => month_range(today.year(), today.month()),
+            "last_month" => match today.month() {
+                Month::January => month_range(today.year() - 1, Month::December),
+                month => month_range(today.year(), month.previous()),
+            },
+            "this_quarter" => quarter_range(today.year(), quarter),
+            "last_quarter" => match quarter {
+                1 => quarter_range(today.year() - 1, 4),
+                quarter => quarter_range(today.year(), quarter - 1),
+            },
+            "year_to_date" => (
+                Date::from_calendar_date(today.year(), Month::January, 1).unwrap(),
+                today,
+            ),
+            period => parse_iso_week(period).ok_or_else(|| {
+                format!(
+                    "unknown period '{}', expected one of {} or an ISO week like 2026-W41",
+                    period, PRESETS
+                )
+            })?,
+        };
+
+        Ok(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use time::macros::date;
+
+    use super::*;
+
+    #[test]
+    fn resolve_presets() {
+        let calendar = Calendar::default();
+        // Wednesday
+        let today = date!(2026 - 01 - 14);
+        let resolve = |period| calendar.resolve(period, today).unwrap();
+
+        assert_eq!(resolve("today"), (today, today));
+        assert_eq!(
+            resolve("yesterday"),
+            (date!(2026 - 01 - 13), date!(2026 - 01 - 13))
+        );
+        assert_eq!(
+            resolve("this_week"),
+            (date!(2026 - 01 - 12), date!(2026 - 01 - 18))
+        );
+        assert_eq!(
+            resolve("last_week"),
+            (date!(2026 - 01 - 05), date!(2026 - 01 - 11))
+        );
+        assert_eq!(
+            resolve("this_month"),
+            (date!(2026 - 01 - 01), date!(2026 - 01 - 31))
+        );
+        assert_eq!(
+            resolve("last_month"),
+            (date!(2025 - 12 - 01), date!(2025 - 12 - 31))
+        );
+        assert_eq!(
+            resolve("this_quarter"),
+            (date!(2026 - 01 - 01), date!(2026 - 03 - 31))
+        );
+        assert_eq!(
+            resolve("last_quarter"),
+            (date!(2025 - 10 - 01), date!(2025 - 12 - 31))
+        );
+        assert_eq!(resolve("year_to_date"), (date!(2026 - 01 - 01), today));
+        assert_eq!(
+            resolve("2026-W41"),
+            (date!(2026 - 10 - 05), date!(2026 - 10 - 11))
+        );
+        assert_eq!(
+            resolve("2026-W01"),
+            (date!(2025 - 12 - 29), date!(2026 - 01 - 04))
+        );
+
+        let sunday_calendar = Calendar {
+            week_start: WeekStart::Sunday,
+            ..calendar
+        };
+        assert_eq!(
+            sunday_calendar.resolve("this_week", today).unwrap(),
+            (date!(2026 - 01 - 11), date!(2026 - 01 - 17))
+        );
+
+        assert!(calendar.resolve("next_week", today).is_err());
+        assert!(calendar.resolve("2026-W54", today).is_err());
+        assert!(calendar.resolve("2026-W1", today).is_err());
+    }
+
+    #[test]
+    fn leap_year_february() {
+        let calendar = Calendar::default();
+
+        assert_eq!(
+            calendar
+                .resolve("last_month", date!(2024 - 03 - 10))
+                .unwrap(),
+            (date!(2024 - 02 - 01), date!(2024 - 02 - 29))
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use log::info;
+use tonic::Status;
+
+use super::redmine_service::{ReportPreset, ReportRequest};
+
+/// Answer formats a preset may ask the HTTP gateway for.
+pub const FORMATS: [&str; 3] = ["json", "markdown", "html"];
+
+/// Named report requests, optionally persisted as a JSON array of `ReportPreset`.
+#[derive(Debug, Clone, Default)]
+pub struct Presets {
+    path: Option<PathBuf>,
+    presets: Arc<RwLock<BTreeMap<String, ReportPreset>>>,
+}
+
+impl Presets {
+    /// Loads presets from `path`, which is created on the first change if missing.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let presets: Vec<ReportPreset> = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("invalid presets in {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err).with_context(|| format!("read {} failed", path.display())),
+        };
+        info!("Loaded {} report presets", presets.len());
+
+        Ok(Self {
+            path: Some(path),
+            presets: Arc::new(RwLock::new(
+                presets
+                    .into_iter()
+                    .map(|preset| (preset.name.clone(), preset))
+                    .collect(),
+            )),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Result<ReportPreset, Status> {
+        self.presets
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("no report preset named '{}'", name)))
+    }
+
+    pub fn list(&self) -> Vec<ReportPreset> {
+        self.presets.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn put(&self, preset: ReportPreset) -> Result<(), Status> {
+        let mut presets = self.presets.write().unwrap();
+        let mut changed = presets.clone();
+        changed.insert(preset.name.clone(), preset);
+
+        self.save(&changed)?;
+        *presets = changed;
+
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), Status> {
+        let mut presets = self.presets.write().unwrap();
+        let mut changed = presets.clone();
+        if changed.remove(name).is_none() {
+            return Err(Status::not_found(format!(
+                "no report preset named '{}'",
+                name
+            )));
+        }
+
+        self.save(&changed)?;
+        *presets = changed;
+
+        Ok(())
+    }
+
+    /// Replaces the file atomically, so a crash never leaves half written presets.
+    fn save(&self, presets: &BTreeMap<String, ReportPreset>) -> Result<(), Status> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let write = || -> Result<()> {
+            let content = serde_json::to_vec_pretty(&presets.values().collect::<Vec<_>>())?;
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, content)
+                .with_context(|| format!("write {} failed", tmp.display()))?;
+            std::fs::rename(&tmp, path)
+                .with_context(|| format!("replace {} failed", path.display()))
+        };
+
+        write().map_err(|err| Status::internal(format!("saving presets failed: {:#}", err)))
+    }
+}
+
+/// Fills the fields `request` leaves unset from `preset`.
+///
+/// Users and the period are taken as a whole, from the request if it sets any of their
+/// fields and from the preset otherwise.
+pub fn apply(preset: &ReportRequest, request: ReportRequest) -> ReportRequest {
+    let has_users =
+        !request.user_id.is_empty() || !request.group_id.is_empty() || !request.login.is_empty();
+    let has_period = !request.period.is_empty()
+        || !request.generate_from_ts.is_empty()
+        || !request.generate_to_ts.is_empty()
+        || request.from_date.is_some()
+        || request.to_date.is_some()
+        || request.from_time.is_some()
+        || request.to_time.is_some();
+
+    let users = if has_users { &request } else { preset };
+    let period = if has_period { &request } else { preset };
+
+    ReportRequest {
+        user_id: users.user_id.clone(),
+        group_id: users.group_id.clone(),
+        login: users.login.clone(),
+        generate_from_ts: period.generate_from_ts.clone(),
+        generate_to_ts: period.generate_to_ts.clone(),
+        period: period.period.clone(),
+        from_date: period.from_date.clone(),
+        to_date: period.to_date.clone(),
+        from_time: period.from_time.clone(),
+        to_time: period.to_time.clone(),
+        impersonate: request.impersonate || preset.impersonate,
+        filter: request.filter.or_else(|| preset.filter.clone()),
+        query_id: match request.query_id {
+            0 => preset.query_id,
+            query_id => query_id,
+        },
+        preset: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::controller::redmine_service::TimeEntryFilter;
+
+    #[test]
+    fn persist_presets() {
+        let path = std::env::temp_dir().join(format!("presets-{}.json", std::process::id()));
+        let preset = ReportPreset {
+            name: "weekly".to_string(),
+            format: "markdown".to_string(),
+            ..Default::default()
+        };
+
+        let presets = Presets::load(path.clone()).unwrap();
+        presets.put(preset.clone()).unwrap();
+        assert_eq!(Presets::load(path.clone()).unwrap().list(), vec![preset]);
+
+        presets.delete("weekly").unwrap();
+        assert!(presets.delete("weekly").is_err());
+        assert_eq!(Presets::load(path.clone()).unwrap().list(), vec![]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn apply_preset() {
+        let preset = ReportRequest {
+            user_id: vec![1, 2],
+            period: "last_week".to_string(),
+            filter: Some(TimeEntryFilter {
+                project_id: vec![3],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            apply(
+                &preset,
+                ReportRequest {
+                    preset: "weekly".to_string(),
+                    ..Default::default()
+                }
+            ),
+            preset
+        );
+
+        assert_eq!(
+            apply(
+                &preset,
+                ReportRequest {
+                    login: vec!["jsmith".to_string()],
+                    generate_from_ts: "2021-01-01".to_string(),
+                    generate_to_ts: "2021-01-31".to_string(),
+                    preset: "weekly".to_string(),
+                    ..Default::default()
+                }
+            ),
+            ReportRequest {
+                login: vec!["jsmith".to_string()],
+                generate_from_ts: "2021-01-01".to_string(),
+                generate_to_ts: "2021-01-31".to_string(),
+                filter: preset.filter.clone(),
+                ..Default::default()
+            }
+        );
+    }
+}
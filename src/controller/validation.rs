@@ -0,0 +1,485 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use log::debug;
+use time::{
+    format_description::well_known::Rfc3339, macros::format_description, Date, Month,
+    OffsetDateTime,
+};
+use tonic::Status;
+
+use super::{
+    google::r#type::Date as GoogleDate,
+    json::timestamp_to_datetime,
+    period::Calendar,
+    redmine_service::{ReportRequest, TimeEntryFilter as FilterRequest},
+};
+use crate::model::TimeEntryFilter;
+
+/// Upper bounds for a single report request.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Longest period in days, both ends included.
+    pub max_days: i64,
+    pub max_users: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_days: 366,
+            max_users: 100,
+        }
+    }
+}
+
+/// A report request that passed validation.
+#[derive(Debug, PartialEq)]
+pub struct ValidRequest {
+    /// Requested users, without duplicates and in the original order.
+    pub user_ids: Vec<u64>,
+    pub group_ids: Vec<u64>,
+    pub logins: Vec<String>,
+    pub filter: TimeEntryFilter,
+    pub impersonate: bool,
+}
+
+fn invalid(field: &str, reason: impl std::fmt::Display) -> Status {
+    Status::invalid_argument(format!("{}: {}", field, reason))
+}
+
+/// Parses a `YYYY-MM-DD` date or an RFC 3339 timestamp taken in the calendar time zone.
+fn parse_date(value: &str, calendar: &Calendar) -> Result<Date, String> {
+    let format = format_description!("[year]-[month]-[day]");
+
+    Date::parse(value, &format)
+        .or_else(|_| OffsetDateTime::parse(value, &Rfc3339).map(|time| calendar.local_date(time)))
+        .map_err(|_| format!("'{}' is not a YYYY-MM-DD date or RFC 3339 timestamp", value))
+}
+
+fn convert_date(date: &GoogleDate) -> Result<Date, String> {
+    if date.year == 0 || date.month == 0 || date.day == 0 {
+        return Err("year, month and day are required".to_string());
+    }
+
+    u8::try_from(date.month)
+        .ok()
+        .and_then(|month| Month::try_from(month).ok())
+        .zip(u8::try_from(date.day).ok())
+        .and_then(|(month, day)| Date::from_calendar_date(date.year, month, day).ok())
+        .ok_or_else(|| {
+            format!(
+                "{:04}-{:02}-{:02} is not a valid date",
+                date.year, date.month, date.day
+            )
+        })
+}
+
+fn ids(field: &str, ids: Vec<u64>) -> Result<HashSet<u64>, Status> {
+    match ids.contains(&0) {
+        true => Err(invalid(field, "0 is not a valid Redmine id")),
+        false => Ok(ids.into_iter().collect()),
+    }
+}
+
+fn convert_filter(filter: FilterRequest, from: Date, to: Date) -> Result<TimeEntryFilter, Status> {
+    if filter.min_hours.is_nan() || filter.min_hours < 0. {
+        return Err(invalid(
+            "filter.min_hours",
+            format!("{} is not a non-negative number", filter.min_hours),
+        ));
+    }
+
+    Ok(TimeEntryFilter {
+        project_ids: ids("filter.project_id", filter.project_id)?,
+        include_subprojects: filter.include_subprojects,
+        activity_ids: ids("filter.activity_id", filter.activity_id)?,
+        tracker_ids: ids("filter.tracker_id", filter.tracker_id)?,
+        issue_ids: ids("filter.issue_id", filter.issue_id)?,
+        min_hours: (filter.min_hours > 0.).then_some(filter.min_hours),
+        ..TimeEntryFilter::new(from, to)
+    })
+}
+
+/// Picks the only set field out of the alternative fields of one period bound.
+fn single_date(
+    fields: [(&'static str, Option<Result<Date, String>>); 3],
+) -> Result<Option<(&'static str, Date)>, Status> {
+    let mut set = fields
+        .into_iter()
+        .filter_map(|(field, value)| Some((field, value?)));
+
+    let (field, value) = match set.next() {
+        Some(field) => field,
+        None => return Ok(None),
+    };
+    if let Some((other, _)) = set.next() {
+        return Err(invalid(
+            field,
+            format!("must not be combined with {}", other),
+        ));
+    }
+
+    value
+        .map(|date| Some((field, date)))
+        .map_err(|err| invalid(field, err))
+}
+
+/// Fails if more users are requested than allowed, also after groups are resolved.
+pub fn check_user_count(count: usize, limits: &Limits) -> Result<(), Status> {
+    if count > limits.max_users {
+        return Err(invalid(
+            "user_id",
+            format!(
+                "{} users requested, at most {} are allowed per request",
+                count, limits.max_users
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate(
+    request: ReportRequest,
+    limits: &Limits,
+    calendar: &Calendar,
+) -> Result<ValidRequest, Status> {
+    let query_id = (request.query_id != 0).then_some(request.query_id);
+    let no_users =
+        request.user_id.is_empty() && request.group_id.is_empty() && request.login.is_empty();
+    if no_users && query_id.is_none() {
+        return Err(invalid(
+            "user_id",
+            "at least one user id, group_id or login is required without query_id",
+        ));
+    }
+    if no_users && request.impersonate {
+        return Err(invalid(
+            "impersonate",
+            "requires user_id, group_id or login",
+        ));
+    }
+    if query_id.is_some() && request.filter.is_some() {
+        return Err(invalid(
+            "filter",
+            "must not be combined with query_id, the saved query defines the filters",
+        ));
+    }
+    if request.user_id.contains(&0) {
+        return Err(invalid("user_id", "0 is not a valid Redmine user id"));
+    }
+
+    let requested = request.user_id.len();
+    let user_ids = request.user_id.into_iter().unique().collect_vec();
+    if user_ids.len() != requested {
+        debug!("Dropped {} duplicated user ids", requested - user_ids.len());
+    }
+    check_user_count(user_ids.len(), limits)?;
+
+    if request.group_id.contains(&0) {
+        return Err(invalid("group_id", "0 is not a valid Redmine group id"));
+    }
+    if request.login.iter().any(|login| login.trim().is_empty()) {
+        return Err(invalid("login", "logins must not be empty"));
+    }
+
+    let non_empty = |value: &str| (!value.is_empty()).then(|| parse_date(value, calendar));
+    let from = single_date([
+        ("generate_from_ts", non_empty(&request.generate_from_ts)),
+        ("from_date", request.from_date.as_ref().map(convert_date)),
+        (
+            "from_time",
+            request
+                .from_time
+                .as_ref()
+                .map(|time| timestamp_to_datetime(time).map(|time| calendar.local_date(time))),
+        ),
+    ])?;
+    let to = single_date([
+        ("generate_to_ts", non_empty(&request.generate_to_ts)),
+        ("to_date", request.to_date.as_ref().map(convert_date)),
+        (
+            "to_time",
+            request
+                .to_time
+                .as_ref()
+                .map(|time| timestamp_to_datetime(time).map(|time| calendar.local_date(time))),
+        ),
+    ])?;
+
+    let (from, to) = match (request.period.as_str(), from, to) {
+        ("", Some((from_field, from)), Some((to_field, to))) => {
+            if from > to {
+                return Err(invalid(
+                    from_field,
+                    format!("{} is after {} {}", from, to_field, to),
+                ));
+            }
+
+            (from, to)
+        }
+        ("", None, _) => {
+            return Err(invalid(
+                "generate_from_ts",
+                "a start date is required (or from_date, from_time, period)",
+            ))
+        }
+        ("", _, None) => {
+            return Err(invalid(
+                "generate_to_ts",
+                "an end date is required (or to_date, to_time, period)",
+            ))
+        }
+        (period, None, None) => calendar
+            .resolve(period, calendar.today())
+            .map_err(|err| invalid("period", err))?,
+        (_, Some((field, _)), _) | (_, _, Some((field, _))) => {
+            return Err(invalid(
+                "period",
+                format!("must not be combined with {}", field),
+            ))
+        }
+    };
+
+    let days = (to - from).whole_days() + 1;
+    if days > limits.max_days {
+        return Err(invalid(
+            "generate_to_ts",
+            format!(
+                "the period of {} days exceeds the maximum of {} days",
+                days, limits.max_days
+            ),
+        ));
+    }
+
+    Ok(ValidRequest {
+        user_ids,
+        group_ids: request.group_id.into_iter().unique().collect(),
+        logins: request
+            .login
+            .iter()
+            .map(|login| login.trim().to_string())
+            .unique()
+            .collect(),
+        filter: TimeEntryFilter {
+            query_id,
+            ..convert_filter(request.filter.unwrap_or_default(), from, to)?
+        },
+        impersonate: request.impersonate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use time::macros::date;
+
+    use super::*;
+
+    fn request(user_id: Vec<u64>, from: &str, to: &str) -> ReportRequest {
+        ReportRequest {
+            user_id,
+            generate_from_ts: from.to_string(),
+            generate_to_ts: to.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn error(request: ReportRequest) -> String {
+        let limits = Limits {
+            max_days: 31,
+            max_users: 3,
+        };
+
+        validate(request, &limits, &Calendar::default())
+            .unwrap_err()
+            .message()
+            .to_string()
+    }
+
+    #[test]
+    fn normalize_request() {
+        assert_eq!(
+            validate(
+                request(vec![3, 1, 3, 2, 1], "2021-01-01", "2021-01-01"),
+                &Limits::default(),
+                &Calendar::default()
+            )
+            .unwrap(),
+            ValidRequest {
+                user_ids: vec![3, 1, 2],
+                group_ids: vec![],
+                logins: vec![],
+                filter: TimeEntryFilter::new(date!(2021 - 01 - 01), date!(2021 - 01 - 01)),
+                impersonate: false,
+            }
+        );
+
+        assert_eq!(
+            validate(
+                ReportRequest {
+                    query_id: 5,
+                    ..request(vec![], "2021-01-01", "2021-01-01")
+                },
+                &Limits::default(),
+                &Calendar::default()
+            )
+            .unwrap(),
+            ValidRequest {
+                user_ids: vec![],
+                group_ids: vec![],
+                logins: vec![],
+                filter: TimeEntryFilter {
+                    query_id: Some(5),
+                    ..TimeEntryFilter::new(date!(2021 - 01 - 01), date!(2021 - 01 - 01))
+                },
+                impersonate: false,
+            }
+        );
+    }
+
+    #[test]
+    fn accept_date_types() {
+        let calendar = Calendar {
+            time_zone: time_tz::timezones::db::europe::MOSCOW,
+            ..Calendar::default()
+        };
+
+        let valid = validate(
+            ReportRequest {
+                from_date: Some(GoogleDate {
+                    year: 2021,
+                    month: 1,
+                    day: 1,
+                }),
+                to_time: Some(prost_types::Timestamp {
+                    seconds: 1612134000, // 2021-01-31T23:00:00Z
+                    nanos: 0,
+                }),
+                ..request(vec![1], "", "")
+            },
+            &Limits::default(),
+            &calendar,
+        )
+        .unwrap();
+        assert_eq!(
+            (valid.filter.from, valid.filter.to),
+            (date!(2021 - 01 - 01), date!(2021 - 02 - 01))
+        );
+
+        let valid = validate(
+            request(
+                vec![1],
+                "2021-01-01T01:00:00+03:00",
+                "2021-01-31T22:00:00-05:00",
+            ),
+            &Limits::default(),
+            &calendar,
+        )
+        .unwrap();
+        assert_eq!(
+            (valid.filter.from, valid.filter.to),
+            (date!(2021 - 01 - 01), date!(2021 - 02 - 01))
+        );
+    }
+
+    #[test]
+    fn reject_request() {
+        assert_eq!(
+            error(request(vec![], "2021-01-01", "2021-01-31")),
+            "user_id: at least one user id, group_id or login is required without query_id"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                query_id: 5,
+                impersonate: true,
+                ..request(vec![], "2021-01-01", "2021-01-31")
+            }),
+            "impersonate: requires user_id, group_id or login"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                login: vec!["jsmith".to_string(), " ".to_string()],
+                ..request(vec![], "2021-01-01", "2021-01-31")
+            }),
+            "login: logins must not be empty"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                query_id: 5,
+                filter: Some(FilterRequest::default()),
+                ..request(vec![1], "2021-01-01", "2021-01-31")
+            }),
+            "filter: must not be combined with query_id, the saved query defines the filters"
+        );
+        assert_eq!(
+            error(request(vec![1, 0], "2021-01-01", "2021-01-31")),
+            "user_id: 0 is not a valid Redmine user id"
+        );
+        assert_eq!(
+            error(request(vec![1, 2, 3, 4, 1], "2021-01-01", "2021-01-31")),
+            "user_id: 4 users requested, at most 3 are allowed per request"
+        );
+        assert_eq!(
+            error(request(vec![1], "", "2021-01-31")),
+            "generate_from_ts: a start date is required (or from_date, from_time, period)"
+        );
+        assert_eq!(
+            error(request(vec![1], "2021-01-01", "2021-02-30")),
+            "generate_to_ts: '2021-02-30' is not a YYYY-MM-DD date or RFC 3339 timestamp"
+        );
+        assert_eq!(
+            error(request(vec![1], "2021-02-01", "2021-01-31")),
+            "generate_from_ts: 2021-02-01 is after generate_to_ts 2021-01-31"
+        );
+        assert_eq!(
+            error(request(vec![1], "2021-01-01", "2021-02-01")),
+            "generate_to_ts: the period of 32 days exceeds the maximum of 31 days"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                period: "this_week".to_string(),
+                ..request(vec![1], "2021-01-01", "")
+            }),
+            "period: must not be combined with generate_from_ts"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                from_date: Some(GoogleDate {
+                    year: 2021,
+                    month: 1,
+                    day: 1
+                }),
+                ..request(vec![1], "2021-01-01", "2021-01-31")
+            }),
+            "generate_from_ts: must not be combined with from_date"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                to_date: Some(GoogleDate {
+                    year: 2021,
+                    month: 2,
+                    day: 29
+                }),
+                ..request(vec![1], "2021-01-01", "")
+            }),
+            "to_date: 2021-02-29 is not a valid date"
+        );
+        assert_eq!(
+            error(ReportRequest {
+                filter: Some(FilterRequest {
+                    min_hours: -1.,
+                    ..Default::default()
+                }),
+                ..request(vec![1], "2021-01-01", "2021-01-31")
+            }),
+            "filter.min_hours: -1 is not a non-negative number"
+        );
+        assert!(error(ReportRequest {
+            period: "fortnight".to_string(),
+            ..request(vec![1], "", "")
+        })
+        .starts_with("period: unknown period 'fortnight'"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    cell::Cell,
+    fmt::{Debug, Display},
+    future::Future,
+    io::Write,
+    str::FromStr,
+};
+
+use anyhow::{bail, Result};
+use log::{LevelFilter, Record};
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Placeholder logged instead of a [`Redacted`] value.
+pub const REDACTED: &str = "[redacted]";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+thread_local! {
+    static REVEAL: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown log format {}, use text or json", format),
+        }
+    }
+}
+
+/// How log lines are written, the level filter itself comes from `RUST_LOG`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Logger {
+    pub format: Format,
+    /// Lines at this level or more severe have their [`Redacted`] values masked.
+    pub redact: LevelFilter,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self {
+            format: Format::Text,
+            redact: LevelFilter::Trace,
+        }
+    }
+}
+
+/// API key, comment or other value only written to lines below the redaction level.
+///
+/// It is masked anywhere else, like in error messages or tracing spans.
+pub struct Redacted<T>(pub T);
+
+impl<T: Display> Display for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match REVEAL.with(Cell::get) {
+            true => self.0.fmt(f),
+            false => f.write_str(REDACTED),
+        }
+    }
+}
+
+impl<T: Debug> Debug for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match REVEAL.with(Cell::get) {
+            true => self.0.fmt(f),
+            false => f.write_str(REDACTED),
+        }
+    }
+}
+
+impl Logger {
+    fn message(&self, record: &Record) -> String {
+        let reveal = record.level() > self.redact;
+
+        REVEAL.with(|cell| {
+            let previous = cell.replace(reveal);
+            let message = record.args().to_string();
+            cell.set(previous);
+            message
+        })
+    }
+
+    /// Formats a record with the id of the request it was logged for, if any.
+    fn line(&self, record: &Record, request_id: Option<&str>) -> String {
+        let message = self.message(record);
+
+        match self.format {
+            Format::Text => match request_id {
+                Some(request_id) => format!(
+                    "[{:<5} {}] [{}] {}",
+                    record.level(),
+                    record.target(),
+                    request_id,
+                    message
+                ),
+                None => format!("[{:<5} {}] {}", record.level(), record.target(), message),
+            },
+            Format::Json => {
+                let mut line = json!({
+                    "timestamp": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message,
+                });
+                if let Some(request_id) = request_id {
+                    line["request_id"] = request_id.into();
+                }
+                line.to_string()
+            }
+        }
+    }
+
+    /// Installs the logger, `RUST_LOG` still selects the levels and modules logged.
+    pub fn init(self) {
+        env_logger::builder()
+            .filter_level(LevelFilter::Info)
+            .parse_default_env()
+            .format(move |buf, record| {
+                let line = REQUEST_ID
+                    .try_with(|request_id| self.line(record, Some(request_id)))
+                    .unwrap_or_else(|_| self.line(record, None));
+                writeln!(buf, "{}", line)
+            })
+            .init();
+    }
+}
+
+/// Runs `future` with every line it logs tagged with `request_id`.
+pub async fn with_request_id<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn redact_by_level() {
+        let logger = Logger {
+            format: Format::Text,
+            redact: LevelFilter::Info,
+        };
+        let line = |level| {
+            logger.line(
+                &Record::builder()
+                    .level(level)
+                    .target("redmine_service::model")
+                    .args(format_args!("comment {}", Redacted("Fixed the login")))
+                    .build(),
+                None,
+            )
+        };
+
+        assert_eq!(
+            line(Level::Info),
+            "[INFO  redmine_service::model] comment [redacted]"
+        );
+        assert_eq!(
+            line(Level::Debug),
+            "[DEBUG redmine_service::model] comment Fixed the login"
+        );
+        assert_eq!(format!("{}", Redacted("Fixed the login")), REDACTED);
+        assert_eq!(format!("{:?}", Redacted("Fixed the login")), REDACTED);
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn json_line() {
+        let logger = Logger {
+            format: Format::Json,
+            ..Logger::default()
+        };
+
+        let line: serde_json::Value = serde_json::from_str(
+            &logger.line(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target("redmine_service::controller")
+                    .args(format_args!("key {}", Redacted("0123456789abcdef")))
+                    .build(),
+                Some("a1b2c3"),
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["target"], "redmine_service::controller");
+        assert_eq!(line["message"], "key [redacted]");
+        assert_eq!(line["request_id"], "a1b2c3");
+        assert!(line["timestamp"].is_string());
+    }
+}
@@ -1,80 +1,260 @@
-use std::env;
+// `tonic::Status` is the error type of choice across controller and view
+#![allow(clippy::result_large_err)]
+
+use std::net::SocketAddr;
 
 use anyhow::{Context, Result};
-use controller::redmine_service::reports_server::ReportsServer;
-use log::info;
+use clap::Parser;
+use config::{Args, Command, Config};
+use controller::{auth::Authenticator, redmine_service::reports_server::ReportsServer};
+use futures::future::BoxFuture;
+use log::{info, warn};
+use shutdown::Shutdown;
+use tls::TlsFiles;
 use tonic::transport::Server;
+use tonic_health::ServingStatus;
 
+mod config;
 mod controller;
+mod logging;
+mod metrics;
 mod model;
-mod view;
-
-fn init_log() {
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
-        .format_timestamp(None)
-        .parse_default_env()
-        .init();
-
-    log::trace!("Log initialization: This is trace message");
-    log::debug!("Log initialization: This is debug message");
-    log::info!("Log initialization: This is info message");
-    log::warn!("Log initialization: This is warn message");
-    log::error!("Log initialization: This is error message");
-}
-
+mod secret;
+mod shutdown;
 #[cfg(feature = "trace")]
-fn init_tracing() {
-    use tracing_subscriber::{fmt, layer::SubscriberExt};
-
-    // Create a tracing layer with the configured tracer
-    let tracer = opentelemetry_jaeger::new_pipeline()
-        .with_service_name("redmine-service")
-        .install_simple()
-        .unwrap();
-    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-
-    tracing::subscriber::set_global_default(
-        fmt::Subscriber::builder()
-            // subscriber configuration
-            .with_max_level(tracing::Level::INFO)
-            .finish()
-            // add additional writers
-            .with(telemetry)
-            .with(fmt::Layer::default()),
-    )
-    .expect("Unable to set global tracing subscriber");
-    log::debug!("Tracing initialized.");
-}
+mod telemetry;
+mod tls;
+mod view;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
 
-    init_log();
+    let args = Args::parse();
+    let config = Config::load(&args)?;
+    config.logger()?.init();
 
     #[cfg(feature = "trace")]
-    init_tracing();
-
-    let addr = env::var("GRPC_ADDR")
-        .with_context(|| "env GRPC_ADDR was not found, please export it".to_string())?
-        .parse()
-        .unwrap();
-
-    info!("Listening on {}", addr);
-
-    Server::builder()
-        .add_service(ReportsServer::new(controller::ReportService::new(
-            env::var("REDMINE_URL")
-                .with_context(|| "env REDMINE_URL was not found, please export it".to_string())?
-                .parse()?,
-            env::var("REDMINE_API_KEY").with_context(|| {
-                "env REDMINE_API_KEY was not found, please export it".to_string()
-            })?,
-        )))
-        .serve(addr)
-        .await
-        .with_context(|| "GRPC Server was not started".to_string())?;
+    telemetry::init()?;
+
+    let result = run(args, config).await;
+
+    #[cfg(feature = "trace")]
+    telemetry::shutdown();
+
+    result
+}
+
+async fn run(args: Args, mut config: Config) -> Result<()> {
+    if args.check_config {
+        config.validate()?;
+        print!("{}", toml::to_string_pretty(&config.masked())?);
+        return Ok(());
+    }
+
+    match args.command {
+        Some(Command::Report(report)) => {
+            let api_key = config.api_key()?.with_context(|| {
+                "redmine.api_key is not set, use REDMINE_API_KEY or REDMINE_API_KEY_FILE"
+                    .to_string()
+            })?;
+            // there is no caller metadata to take another key from
+            config.redmine.api_key_fallback = true;
+
+            let service = config.report_service(Some(api_key))?;
+            return controller::cli::report(&service, report).await;
+        }
+        Some(Command::Client(client)) => {
+            let server = match &client.server {
+                Some(server) => server.clone(),
+                None => format!(
+                    "{}://{}",
+                    match config.server.tls_cert {
+                        Some(_) => "https",
+                        None => "http",
+                    },
+                    config.grpc_addr()?
+                ),
+            };
+            return controller::client::report(&server, client).await;
+        }
+        None => {}
+    }
+
+    let addr = config.grpc_addr()?;
+
+    let auth_tokens = config.auth_tokens()?;
+    let authenticator = config.authenticator(auth_tokens.as_ref())?;
+    if config.server.insecure_no_auth {
+        warn!("server.insecure_no_auth is set, client authentication is disabled");
+    }
+
+    let api_key = config.api_key()?;
+    if api_key.is_none() {
+        warn!("redmine.api_key is not set, callers must pass their own Redmine API key");
+    }
+    config.watch_secrets(api_key.as_ref(), auth_tokens.as_ref(), &authenticator);
+
+    if config.audit.file.is_none() {
+        warn!("audit.file is not set, the audit log is kept in memory only");
+    }
+
+    let tls = config.tls()?;
+
+    let service = config.report_service(api_key)?;
+
+    let grace_period = config.grace_period();
+    let shutdown = Shutdown::on_signals(config.drain_delay())?;
+
+    let mut servers: Vec<BoxFuture<Result<()>>> = vec![Box::pin(serve_grpc(
+        addr,
+        tls,
+        service.clone(),
+        authenticator.clone(),
+        shutdown.clone(),
+    ))];
+    if let Some(http_addr) = config.server.http_addr {
+        servers.push(Box::pin(serve_http(
+            http_addr,
+            service,
+            authenticator,
+            shutdown.clone(),
+        )));
+    }
+    if let Some(metrics_addr) = config.server.metrics_addr {
+        servers.push(Box::pin(serve_metrics(metrics_addr, shutdown.clone())));
+    }
+    let mut servers = futures::future::try_join_all(servers);
+
+    tokio::select! {
+        // servers also stop once drained, which is no failure
+        biased;
+        _ = shutdown.drained() => {}
+        result = &mut servers => return result.map(|_| ()),
+    }
+
+    // the servers stopped accepting, in-flight calls may finish within the grace period
+    match tokio::time::timeout(grace_period, servers).await {
+        Ok(result) => {
+            result?;
+        }
+        Err(_) => warn!(
+            "In-flight requests were aborted after the grace period of {:?}",
+            grace_period
+        ),
+    }
+    info!("Shut down");
 
     Ok(())
 }
+
+fn grpc_server() -> Server {
+    // gRPC-Web clients talk HTTP/1.1 to the same port
+    let server = Server::builder().accept_http1(true);
+    #[cfg(feature = "trace")]
+    let server = server.trace_fn(telemetry::grpc_span);
+
+    server
+}
+
+async fn serve_grpc(
+    addr: SocketAddr,
+    tls: Option<TlsFiles>,
+    service: controller::ReportService,
+    authenticator: Authenticator,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let (mut health, health_service) = tonic_health::server::health_reporter();
+    health
+        .set_serving::<ReportsServer<controller::ReportService>>()
+        .await;
+    // the whole server, checked by probes that name no service
+    health.set_service_status("", ServingStatus::Serving).await;
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.requested().await;
+            health
+                .set_not_serving::<ReportsServer<controller::ReportService>>()
+                .await;
+            health
+                .set_service_status("", ServingStatus::NotServing)
+                .await;
+        }
+    });
+
+    let service = tonic_web::enable(metrics::Rpcs(ReportsServer::with_interceptor(
+        service,
+        authenticator,
+    )));
+
+    let tls = match tls {
+        Some(tls) => tls,
+        None => {
+            info!("Listening on {}", addr);
+
+            return grpc_server()
+                .add_service(health_service)
+                .add_service(service)
+                .serve_with_shutdown(addr, shutdown.drained())
+                .await
+                .with_context(|| "GRPC Server was not started".to_string());
+        }
+    };
+
+    // TLS is terminated here, so rotated certificates are picked up without closing the listener
+    let acceptor = tls::Acceptor::new(tls)?;
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("unable to listen on {}", addr))?;
+    info!("Listening on {} with TLS", addr);
+    tokio::spawn(acceptor.clone().watch());
+
+    grpc_server()
+        .add_service(health_service)
+        .add_service(service)
+        .serve_with_incoming_shutdown(acceptor.incoming(listener), shutdown.drained())
+        .await
+        .with_context(|| "GRPC Server was not started".to_string())
+}
+
+async fn serve_http(
+    addr: SocketAddr,
+    service: controller::ReportService,
+    authenticator: Authenticator,
+    shutdown: Shutdown,
+) -> Result<()> {
+    info!("HTTP gateway listening on {}", addr);
+
+    axum::Server::bind(&addr)
+        .serve(
+            controller::gateway::router(service, authenticator)
+                .into_make_service_with_connect_info::<SocketAddr, _>(),
+        )
+        .with_graceful_shutdown(async move { shutdown.drained().await })
+        .await
+        .with_context(|| "HTTP Server was not started".to_string())
+}
+
+async fn serve_metrics(addr: SocketAddr, shutdown: Shutdown) -> Result<()> {
+    info!("Metrics listening on {}", addr);
+
+    let router = axum::Router::new().route(
+        "/metrics",
+        axum::routing::get(|| async {
+            (
+                axum::response::Headers([(
+                    axum::http::header::CONTENT_TYPE,
+                    prometheus::TEXT_FORMAT,
+                )]),
+                metrics::render(),
+            )
+        }),
+    );
+
+    axum::Server::bind(&addr)
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(async move { shutdown.drained().await })
+        .await
+        .with_context(|| "Metrics Server was not started".to_string())
+}
@@ -0,0 +1,494 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::LazyLock,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::body::Bytes;
+use itertools::Itertools;
+use prometheus::{
+    core::Collector, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Body, BoxFuture, Service},
+    transport::NamedService,
+    Code, Status,
+};
+
+const NAMESPACE: &str = "redmine_service";
+/// Methods of the Reports service, any other path is labelled `unknown`.
+const RPCS: [&str; 12] = [
+    "GenerateReport",
+    "ListPresets",
+    "PutPreset",
+    "DeletePreset",
+    "ListUsers",
+    "ListProjects",
+    "ListActivities",
+    "SubmitReport",
+    "GetReportStatus",
+    "GetReportResult",
+    "CancelReport",
+    "QueryAuditLog",
+];
+
+struct Metrics {
+    registry: Registry,
+    rpc_requests: IntCounterVec,
+    rpc_duration: HistogramVec,
+    redmine_requests: IntCounterVec,
+    redmine_errors: IntCounterVec,
+    redmine_duration: HistogramVec,
+    redmine_pages: IntCounterVec,
+    cache_lookups: IntCounterVec,
+    reports_in_flight: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(NAMESPACE);
+        let histogram_opts = |name: &str, help: &str| {
+            HistogramOpts::new(name, help)
+                .namespace(NAMESPACE)
+                // Redmine pages take tens of milliseconds, whole reports up to minutes
+                .buckets(vec![
+                    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+                ])
+        };
+
+        let metrics = Self {
+            registry: Registry::new(),
+            rpc_requests: IntCounterVec::new(
+                opts(
+                    "rpc_requests_total",
+                    "Handled RPCs by method and status code",
+                ),
+                &["rpc", "code"],
+            )
+            .unwrap(),
+            rpc_duration: HistogramVec::new(
+                histogram_opts(
+                    "rpc_duration_seconds",
+                    "RPC handling time by method and status code",
+                ),
+                &["rpc", "code"],
+            )
+            .unwrap(),
+            redmine_requests: IntCounterVec::new(
+                opts("redmine_requests_total", "Redmine API calls by endpoint"),
+                &["endpoint"],
+            )
+            .unwrap(),
+            redmine_errors: IntCounterVec::new(
+                opts(
+                    "redmine_request_errors_total",
+                    "Failed Redmine API calls by endpoint",
+                ),
+                &["endpoint"],
+            )
+            .unwrap(),
+            redmine_duration: HistogramVec::new(
+                histogram_opts(
+                    "redmine_request_duration_seconds",
+                    "Redmine API call time by endpoint",
+                ),
+                &["endpoint"],
+            )
+            .unwrap(),
+            redmine_pages: IntCounterVec::new(
+                opts(
+                    "redmine_pages_fetched_total",
+                    "Pages of paginated Redmine collections fetched by endpoint",
+                ),
+                &["endpoint"],
+            )
+            .unwrap(),
+            cache_lookups: IntCounterVec::new(
+                opts(
+                    "cache_lookups_total",
+                    "Discovery cache lookups by cache and result (hit or miss)",
+                ),
+                &["cache", "result"],
+            )
+            .unwrap(),
+            reports_in_flight: IntGauge::with_opts(opts(
+                "reports_in_flight",
+                "Reports being fetched from Redmine",
+            ))
+            .unwrap(),
+        };
+
+        let collectors: [Box<dyn Collector>; 8] = [
+            Box::new(metrics.rpc_requests.clone()),
+            Box::new(metrics.rpc_duration.clone()),
+            Box::new(metrics.redmine_requests.clone()),
+            Box::new(metrics.redmine_errors.clone()),
+            Box::new(metrics.redmine_duration.clone()),
+            Box::new(metrics.redmine_pages.clone()),
+            Box::new(metrics.cache_lookups.clone()),
+            Box::new(metrics.reports_in_flight.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// An RPC being handled, recorded as `Cancelled` if dropped before it finished.
+struct Call {
+    rpc: String,
+    started: Instant,
+    finished: bool,
+}
+
+impl Call {
+    fn start(rpc: &str) -> Self {
+        Self {
+            rpc: rpc.to_string(),
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Counts and times the call by `code`, only the first time.
+    fn finish(&mut self, code: Code) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+
+        let code = format!("{:?}", code);
+        METRICS
+            .rpc_requests
+            .with_label_values(&[&self.rpc, &code])
+            .inc();
+        METRICS
+            .rpc_duration
+            .with_label_values(&[&self.rpc, &code])
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.finish(Code::Cancelled);
+    }
+}
+
+/// Handles an RPC outside of a gRPC server, counting it and timing it by its status code.
+pub async fn rpc<T>(
+    rpc: &str,
+    handler: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+    let mut call = Call::start(rpc);
+    let result = handler.await;
+
+    call.finish(match &result {
+        Ok(_) => Code::Ok,
+        Err(status) => status.code(),
+    });
+
+    result
+}
+
+/// Counts and times every call of a gRPC service by method and status code.
+///
+/// The code of a streamed response is only known from its trailers, so the call is
+/// recorded once the body is done.
+#[derive(Debug, Clone)]
+pub struct Rpcs<S>(pub S);
+
+impl<S: NamedService> NamedService for Rpcs<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// The method called at `path` of `service`, so that clients cannot make up labels.
+fn rpc_label(service: &str, path: &str) -> &'static str {
+    path.strip_prefix('/')
+        .and_then(|path| path.strip_prefix(service))
+        .and_then(|path| path.strip_prefix('/'))
+        .and_then(|method| RPCS.into_iter().find(|rpc| *rpc == method))
+        .unwrap_or("unknown")
+}
+
+impl<S, B> Service<http::Request<B>> for Rpcs<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + NamedService,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let mut call = Call::start(rpc_label(S::NAME, request.uri().path()));
+        let response = self.0.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            // errors before any message are answered without a body
+            if let Some(status) = Status::from_header_map(response.headers()) {
+                call.finish(status.code());
+            }
+
+            Ok(response.map(|inner| RecordedBody { inner, call }.boxed_unsync()))
+        })
+    }
+}
+
+struct RecordedBody {
+    inner: BoxBody,
+    call: Call,
+}
+
+impl Body for RecordedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = ready!(Pin::new(&mut self.inner).poll_data(cx));
+        if let Some(Err(status)) = &data {
+            self.call.finish(status.code());
+        }
+        Poll::Ready(data)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        match &trailers {
+            Ok(trailers) => {
+                if let Some(status) = trailers.as_ref().and_then(Status::from_header_map) {
+                    self.call.finish(status.code());
+                }
+            }
+            Err(status) => self.call.finish(status.code()),
+        }
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// Endpoint with ids replaced, so `users/12` and `users/15` share the `users/:id` label.
+fn endpoint_label(endpoint: &str) -> String {
+    endpoint
+        .split('/')
+        .map(|segment| match segment.parse::<u64>() {
+            Ok(_) => ":id",
+            Err(_) => segment,
+        })
+        .join("/")
+}
+
+pub fn redmine_request(endpoint: &str, elapsed: Duration, succeeded: bool) {
+    let endpoint = endpoint_label(endpoint);
+
+    METRICS
+        .redmine_requests
+        .with_label_values(&[&endpoint])
+        .inc();
+    if !succeeded {
+        METRICS.redmine_errors.with_label_values(&[&endpoint]).inc();
+    }
+    METRICS
+        .redmine_duration
+        .with_label_values(&[&endpoint])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn redmine_page(endpoint: &str) {
+    METRICS
+        .redmine_pages
+        .with_label_values(&[&endpoint_label(endpoint)])
+        .inc();
+}
+
+pub fn cache_lookup(cache: &str, hit: bool) {
+    METRICS
+        .cache_lookups
+        .with_label_values(&[cache, if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+/// Counts a report as in flight until dropped.
+pub struct ReportInFlight(());
+
+impl ReportInFlight {
+    pub fn start() -> Self {
+        METRICS.reports_in_flight.inc();
+        Self(())
+    }
+}
+
+impl Drop for ReportInFlight {
+    fn drop(&mut self) {
+        METRICS.reports_in_flight.dec();
+    }
+}
+
+/// All metrics in the Prometheus text format.
+pub fn render() -> String {
+    TextEncoder::new()
+        .encode_to_string(&METRICS.registry.gather())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn label_rpcs() {
+        assert_eq!(
+            rpc_label("redmine_api.Reports", "/redmine_api.Reports/ListUsers"),
+            "ListUsers"
+        );
+        for path in [
+            "/redmine_api.Reports/Unknown",
+            "/other.Service/ListUsers",
+            "/redmine_api.Reports/ListUsers/extra",
+            "",
+        ] {
+            assert_eq!(rpc_label("redmine_api.Reports", path), "unknown");
+        }
+    }
+
+    #[test]
+    fn label_endpoints() {
+        assert_eq!(endpoint_label("time_entries"), "time_entries");
+        assert_eq!(endpoint_label("users/12"), "users/:id");
+        assert_eq!(
+            endpoint_label("enumerations/time_entry_activities"),
+            "enumerations/time_entry_activities"
+        );
+    }
+
+    /// Empty body ending with the trailers of a successful call.
+    struct OkTrailers(Option<http::HeaderMap>);
+
+    impl Body for OkTrailers {
+        type Data = Bytes;
+        type Error = Status;
+
+        fn poll_data(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Bytes, Status>>> {
+            Poll::Ready(None)
+        }
+
+        fn poll_trailers(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Option<http::HeaderMap>, Status>> {
+            Poll::Ready(Ok(self.0.take()))
+        }
+    }
+
+    /// Answers every call with `code`, the way tonic does.
+    struct Answer(Code);
+
+    impl NamedService for Answer {
+        const NAME: &'static str = "redmine_api.Reports";
+    }
+
+    impl Service<http::Request<()>> for Answer {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = futures::future::Ready<Result<Self::Response, Status>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Status>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            futures::future::ok(match self.0 {
+                Code::Ok => {
+                    let mut trailers = http::HeaderMap::new();
+                    trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+                    http::Response::new(OkTrailers(Some(trailers)).boxed_unsync())
+                }
+                code => Status::new(code, "").to_http(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn record_finished_and_dropped_rpcs() {
+        let call = |code, rpc: &str| {
+            Rpcs(Answer(code)).call(
+                http::Request::post(format!("/redmine_api.Reports/{}", rpc))
+                    .body(())
+                    .unwrap(),
+            )
+        };
+        let recorded = |rpc: &str, code: &str| {
+            render().contains(&format!(
+                r#"redmine_service_rpc_requests_total{{code="{}",rpc="{}"}} 1"#,
+                code, rpc
+            ))
+        };
+
+        let mut body = call(Code::Ok, "ListPresets").await.unwrap().into_body();
+        assert!(!recorded("ListPresets", "Ok"));
+        body.trailers().await.unwrap();
+        assert!(recorded("ListPresets", "Ok"));
+
+        call(Code::PermissionDenied, "PutPreset").await.unwrap();
+        assert!(recorded("PutPreset", "PermissionDenied"));
+
+        // the client went away before the trailers
+        drop(call(Code::Ok, "CancelReport").await.unwrap());
+        assert!(recorded("CancelReport", "Cancelled"));
+
+        call(Code::Unimplemented, "Made/Up").await.unwrap();
+        assert!(recorded("unknown", "Unimplemented"));
+
+        let pending = rpc("ListUsers", std::future::pending::<Result<(), Status>>());
+        tokio::time::timeout(Duration::ZERO, pending)
+            .await
+            .unwrap_err();
+        assert!(recorded("ListUsers", "Cancelled"));
+    }
+
+    #[tokio::test]
+    async fn render_metrics() {
+        rpc("GenerateReport", async {
+            Err::<(), _>(Status::not_found(""))
+        })
+        .await
+        .unwrap_err();
+        redmine_request("groups/5", Duration::from_millis(20), false);
+        cache_lookup("users", true);
+
+        let metrics = render();
+        assert!(metrics.contains(
+            r#"redmine_service_rpc_requests_total{code="NotFound",rpc="GenerateReport"} 1"#
+        ));
+        assert!(metrics
+            .contains(r#"redmine_service_redmine_request_errors_total{endpoint="groups/:id"} 1"#));
+        assert!(metrics
+            .contains(r#"redmine_service_cache_lookups_total{cache="users",result="hit"} 1"#));
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use reqwest::{Certificate, Identity, NoProxy, Proxy};
+
+/// Transport settings of the HTTP client used to reach Redmine.
+///
+/// Without an explicit `proxy` the standard `HTTP(S)_PROXY`/`NO_PROXY` env vars apply.
+#[derive(Debug, Default)]
+pub struct ClientOptions {
+    /// PEM bundle with extra root certificates, e.g. an internal CA.
+    pub root_certificates: Option<PathBuf>,
+    /// PEM certificate chain and PKCS#8 key for client certificate authentication.
+    pub client_certificate: Option<(PathBuf, PathBuf)>,
+    pub proxy: Option<String>,
+    /// Comma separated hosts, domains and IP networks reached without the proxy.
+    pub no_proxy: Option<String>,
+}
+
+impl ClientOptions {
+    pub fn build(&self) -> Result<reqwest::Client> {
+        let read = |path: &PathBuf| {
+            std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))
+        };
+
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(path) = &self.root_certificates {
+            for certificate in Certificate::from_pem_bundle(&read(path)?)
+                .with_context(|| format!("invalid certificates in {}", path.display()))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        if let Some((cert, key)) = &self.client_certificate {
+            builder = builder.identity(
+                Identity::from_pkcs8_pem(&read(cert)?, &read(key)?)
+                    .with_context(|| "invalid client certificate or key".to_string())?,
+            );
+        }
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(
+                Proxy::all(proxy)
+                    .with_context(|| format!("invalid proxy {}", proxy))?
+                    .no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string)),
+            );
+        }
+
+        builder
+            .build()
+            .with_context(|| "unable to build HTTP client".to_string())
+    }
+}
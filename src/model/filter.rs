@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use time::{macros::format_description, Date};
+
+use super::TimeEntry;
+
+/// Selects time entries, translated into Redmine query parameters where Redmine supports
+/// them and checked locally with [`TimeEntryFilter::matches`] otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeEntryFilter {
+    pub from: Date,
+    pub to: Date,
+    /// Saved Redmine time entry query, applied by Redmine on top of the other filters.
+    pub query_id: Option<u64>,
+    pub project_ids: HashSet<u64>,
+    /// Also take entries of subprojects of `project_ids`.
+    pub include_subprojects: bool,
+    pub activity_ids: HashSet<u64>,
+    /// Trackers of the issues the time was spent on.
+    pub tracker_ids: HashSet<u64>,
+    pub issue_ids: HashSet<u64>,
+    pub min_hours: Option<f64>,
+}
+
+fn join(ids: &HashSet<u64>) -> String {
+    ids.iter().sorted().join("|")
+}
+
+impl TimeEntryFilter {
+    pub fn new(from: Date, to: Date) -> Self {
+        Self {
+            from,
+            to,
+            query_id: None,
+            project_ids: HashSet::new(),
+            include_subprojects: false,
+            activity_ids: HashSet::new(),
+            tracker_ids: HashSet::new(),
+            issue_ids: HashSet::new(),
+            min_hours: None,
+        }
+    }
+
+    /// Redmine scopes time entries to a single project, so every project is a separate query.
+    pub(super) fn project_scopes(&self) -> Vec<Option<u64>> {
+        if self.project_ids.is_empty() {
+            vec![None]
+        } else {
+            self.project_ids
+                .iter()
+                .sorted()
+                .copied()
+                .map(Some)
+                .collect()
+        }
+    }
+
+    /// Query parameters for `/time_entries.json` within the given project scope.
+    pub(super) fn query(&self, project_id: Option<u64>) -> Vec<(&'static str, String)> {
+        let format = format_description!("[year]-[month]-[day]");
+        let mut query = vec![
+            ("from", self.from.format(&format).unwrap()),
+            ("to", self.to.format(&format).unwrap()),
+        ];
+
+        if let Some(query_id) = self.query_id {
+            query.push(("query_id", query_id.to_string()));
+        }
+        if let Some(project_id) = project_id {
+            query.push(("project_id", project_id.to_string()));
+            if !self.include_subprojects {
+                query.push(("subproject_id", "!*".to_string()));
+            }
+        }
+        if !self.activity_ids.is_empty() {
+            query.push(("activity_id", join(&self.activity_ids)));
+        }
+        if !self.tracker_ids.is_empty() {
+            query.push(("issue.tracker_id", join(&self.tracker_ids)));
+        }
+        if !self.issue_ids.is_empty() {
+            // Redmine's issue filter takes a comma separated list in a single value
+            query.push(("issue_id", self.issue_ids.iter().sorted().join(",")));
+        }
+        if let Some(min_hours) = self.min_hours {
+            query.push(("hours", format!(">={}", min_hours)));
+        }
+
+        query
+    }
+
+    /// Checks everything known from the time entry itself, trackers need the issue.
+    pub fn matches(&self, time_entry: &TimeEntry) -> bool {
+        (self.include_subprojects
+            || self.project_ids.is_empty()
+            || self.project_ids.contains(&time_entry.project.id))
+            && (self.activity_ids.is_empty() || self.activity_ids.contains(&time_entry.activity.id))
+            && (self.issue_ids.is_empty() || self.issue_ids.contains(&time_entry.issue.id))
+            && self
+                .min_hours
+                .is_none_or(|min_hours| time_entry.hours >= min_hours)
+            && (self.from..=self.to).contains(&time_entry.spent_on)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use time::macros::date;
+
+    use super::*;
+
+    #[test]
+    fn build_query() {
+        let filter = TimeEntryFilter {
+            project_ids: [7, 3].into_iter().collect(),
+            activity_ids: [9, 8].into_iter().collect(),
+            tracker_ids: [1].into_iter().collect(),
+            issue_ids: [42, 5].into_iter().collect(),
+            min_hours: Some(0.5),
+            ..TimeEntryFilter::new(date!(2021 - 01 - 01), date!(2021 - 01 - 31))
+        };
+
+        assert_eq!(filter.project_scopes(), vec![Some(3), Some(7)]);
+        assert_eq!(
+            filter.query(Some(3)),
+            vec![
+                ("from", "2021-01-01".to_string()),
+                ("to", "2021-01-31".to_string()),
+                ("project_id", "3".to_string()),
+                ("subproject_id", "!*".to_string()),
+                ("activity_id", "8|9".to_string()),
+                ("issue.tracker_id", "1".to_string()),
+                ("issue_id", "5,42".to_string()),
+                ("hours", ">=0.5".to_string()),
+            ]
+        );
+
+        let filter = TimeEntryFilter {
+            query_id: Some(12),
+            include_subprojects: true,
+            ..TimeEntryFilter::new(date!(2021 - 01 - 01), date!(2021 - 01 - 31))
+        };
+
+        assert_eq!(filter.project_scopes(), vec![None]);
+        assert_eq!(
+            filter.query(None),
+            vec![
+                ("from", "2021-01-01".to_string()),
+                ("to", "2021-01-31".to_string()),
+                ("query_id", "12".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Redmine stand-in for tests, answering from a closure and recording every request.
+
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::Extension,
+    handler::Handler,
+    http::{HeaderMap, StatusCode, Uri},
+    Json, Router,
+};
+use serde_json::Value;
+
+use super::Redmine;
+use crate::secret::Secret;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Endpoint without the leading slash, like `users.json`.
+    pub path: String,
+    pub query: String,
+    pub api_key: Option<String>,
+    pub switch_user: Option<String>,
+}
+
+type Answer = dyn Fn(&Request) -> (StatusCode, Value) + Send + Sync;
+
+#[derive(Clone)]
+struct State {
+    answer: Arc<Answer>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+async fn handle(
+    uri: Uri,
+    headers: HeaderMap,
+    Extension(state): Extension<State>,
+) -> (StatusCode, Json<Value>) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let request = Request {
+        path: uri.path().trim_start_matches('/').to_string(),
+        query: uri.query().unwrap_or_default().to_string(),
+        api_key: header(super::AUTHORIZATION_HEADER),
+        switch_user: header(super::SWITCH_USER_HEADER),
+    };
+
+    let (status, body) = (state.answer)(&request);
+    state.requests.lock().unwrap().push(request);
+    (status, Json(body))
+}
+
+pub struct MockRedmine {
+    url: reqwest::Url,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockRedmine {
+    /// Serves `answer` on a free local port, needs a Tokio runtime.
+    pub fn start(answer: impl Fn(&Request) -> (StatusCode, Value) + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let router = Router::new()
+            .fallback(handle.into_service())
+            .layer(Extension(State {
+                answer: Arc::new(answer),
+                requests: requests.clone(),
+            }));
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        Self { url, requests }
+    }
+
+    pub fn redmine(&self, api_key: Option<&str>) -> Redmine {
+        Redmine::new(
+            reqwest::Client::builder().no_proxy().build().unwrap(),
+            self.url.clone(),
+            api_key.map(|api_key| Secret::new(api_key.to_string())),
+        )
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -1,74 +1,262 @@
-use std::fmt::Display;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
 
 use anyhow::{Context, Result};
+pub use client::ClientOptions;
+pub use filter::TimeEntryFilter;
 use itertools::Itertools;
-use log::{debug, info};
+use log::{debug, info, trace};
+use reqwest::header::HeaderValue;
 use serde::{de::DeserializeOwned, Deserialize};
-use time::{macros::format_description, Date};
+use tokio::time::Instant;
 #[cfg(feature = "trace")]
 use tracing::instrument;
-pub use types::{issue::Issue, time_entry::TimeEntry};
+pub use types::{
+    activity::Activity, group::Group, issue::Issue, project::Project, time_entry::TimeEntry,
+    user::User,
+};
+
+use crate::{logging::Redacted, metrics, secret::Secret};
+
+pub mod client;
+pub mod filter;
+#[cfg(test)]
+pub mod mock;
 pub mod types;
 
 const AUTHORIZATION_HEADER: &str = "X-Redmine-API-Key";
-const LIMIT: usize = 100;
+const SWITCH_USER_HEADER: &str = "X-Redmine-Switch-User";
+/// Redmine's own default maximum of `limit`.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// Redmine answers at most this many items, whatever `limit` asks for.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// No Redmine user has the given login or email.
+#[derive(Debug)]
+pub struct UnknownUser(pub String);
+
+impl Display for UnknownUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no Redmine user with login or email '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownUser {}
+
+/// The deadline set with [`Redmine::with_deadline`] passed before Redmine answered.
+#[derive(Debug)]
+pub struct DeadlineExceeded;
+
+impl Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline exceeded")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
 
+#[derive(Clone)]
 pub struct Redmine {
+    client: reqwest::Client,
     site: reqwest::Url,
-    api_key: String,
+    api_key: Option<Secret>,
+    switch_user: Option<String>,
+    deadline: Option<Instant>,
+    page_size: usize,
 }
 
 impl std::fmt::Debug for Redmine {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Redmine")
             .field("site", &self.site)
-            .field("api_key", &"PRIVATE")
+            .field("api_key", &self.api_key)
+            .field("switch_user", &self.switch_user)
+            .field("deadline", &self.deadline)
+            .field("page_size", &self.page_size)
             .finish()
     }
 }
 
 impl Redmine {
-    pub fn new(site: reqwest::Url, api_key: String) -> Self {
-        Self { site, api_key }
+    pub fn new(client: reqwest::Client, site: reqwest::Url, api_key: Option<Secret>) -> Self {
+        Self {
+            client,
+            site,
+            api_key,
+            switch_user: None,
+            deadline: None,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Sets how many items are asked for per request, Redmine answers at most
+    /// [`MAX_PAGE_SIZE`].
+    pub fn with_page_size(self, page_size: usize) -> Self {
+        Self { page_size, ..self }
+    }
+
+    /// Returns a client acting with the given API key instead of the service one.
+    pub fn with_api_key(&self, api_key: String) -> Self {
+        Self {
+            api_key: Some(Secret::new(api_key)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a client impersonating `login`, so Redmine applies that user's visibility.
+    ///
+    /// Requires the API key to belong to a Redmine administrator.
+    pub fn switch_user(&self, login: String) -> Self {
+        Self {
+            switch_user: Some(login),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a client giving up on Redmine calls once `deadline` has passed.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self.clone()
+        }
+    }
+
+    pub fn has_api_key(&self) -> bool {
+        self.api_key.is_some()
     }
 
     #[cfg_attr(feature = "trace", instrument)]
-    async fn get_api<'de, T, I, K, V>(&self, endpoint: &str, options: I, offset: usize) -> Result<T>
+    async fn get_api<T, I, K, V>(&self, endpoint: &str, options: I, offset: usize) -> Result<T>
     where
         T: DeserializeOwned + std::fmt::Debug,
         I: Iterator<Item = (K, V)> + std::fmt::Debug,
         K: Display + std::fmt::Debug,
         V: Display + std::fmt::Debug,
     {
-        let url = format!(
-            "{}/{}.json?offset={}&limit={}&{}",
-            self.site,
-            endpoint,
-            offset,
-            LIMIT,
-            options
-                .map(|(name, value)| format!("{}={}", name, value))
-                .join("&"),
-        );
+        let url = reqwest::Url::parse_with_params(
+            &format!("{}/{}.json", self.site, endpoint),
+            [
+                ("offset".to_string(), offset.to_string()),
+                ("limit".to_string(), self.page_size.to_string()),
+            ]
+            .into_iter()
+            .chain(options.map(|(name, value)| (name.to_string(), value.to_string()))),
+        )
+        .with_context(|| format!("invalid url for {}", endpoint))?;
 
         debug!("try to call {}", url);
-        reqwest::Client::new()
-            .get(&url)
-            .header(AUTHORIZATION_HEADER, &self.api_key)
-            .send()
-            .await
-            .with_context(|| format!("get {} failed", url))?
-            .json()
-            .await
-            .map_err(|err| err.into())
+        let mut request = self.client.get(url.clone());
+        if let Some(api_key) = &self.api_key {
+            let mut api_key = HeaderValue::from_str(&api_key.expose())
+                .with_context(|| "the Redmine API key is not a valid header value".to_string())?;
+            api_key.set_sensitive(true);
+            request = request.header(AUTHORIZATION_HEADER, api_key);
+        }
+        if let Some(login) = &self.switch_user {
+            request = request.header(SWITCH_USER_HEADER, login);
+        }
+        #[cfg(feature = "trace")]
+        {
+            let mut headers = reqwest::header::HeaderMap::new();
+            crate::telemetry::inject(&mut headers);
+            request = request.headers(headers);
+        }
+
+        let response = async {
+            request
+                .send()
+                .await
+                .with_context(|| format!("get {} failed", url))?
+                .error_for_status()?
+                .json()
+                .await
+                .map_err(|err| err.into())
+        };
+
+        let started = Instant::now();
+        let result = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, response)
+                .await
+                .unwrap_or_else(|_| Err(DeadlineExceeded.into())),
+            None => response.await,
+        };
+        metrics::redmine_request(endpoint, started.elapsed(), result.is_ok());
+
+        result
     }
 
+    /// Fetches every page of a collection endpoint like `projects`.
+    #[cfg_attr(feature = "trace", instrument)]
+    async fn get_all<T>(
+        &self,
+        endpoint: &str,
+        collection: &str,
+        options: &[(&str, &str)],
+    ) -> Result<Vec<T>>
+    where
+        T: DeserializeOwned + std::fmt::Debug,
+    {
+        let mut items = Vec::new();
+
+        loop {
+            let mut page: serde_json::Value = self
+                .get_api(endpoint, options.iter().copied(), items.len())
+                .await
+                .with_context(|| format!("get {} failed", endpoint))?;
+
+            metrics::redmine_page(endpoint);
+
+            let batch: Vec<T> = serde_json::from_value(page[collection].take())
+                .with_context(|| format!("malformed {} in {}", collection, endpoint))?;
+            // unpaginated endpoints have no total_count
+            let total_count = page["total_count"].as_u64().unwrap_or(0) as usize;
+
+            let last = batch.is_empty();
+            items.extend(batch);
+            if last || items.len() >= total_count {
+                break;
+            }
+        }
+        info!("Fetched {} {}", items.len(), collection);
+
+        Ok(items)
+    }
+
+    /// Active users, needs an administrator API key.
+    pub async fn get_users(&self) -> Result<Vec<User>> {
+        self.get_all("users", "users", &[]).await
+    }
+
+    pub async fn get_projects(&self) -> Result<Vec<Project>> {
+        self.get_all("projects", "projects", &[]).await
+    }
+
+    pub async fn get_activities(&self) -> Result<Vec<Activity>> {
+        self.get_all(
+            "enumerations/time_entry_activities",
+            "time_entry_activities",
+            &[],
+        )
+        .await
+    }
+
+    /// Identifies the credentials for caches, without keeping the API key itself around.
+    pub fn cache_key(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (self.api_key.as_ref().map(Secret::expose), &self.switch_user).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Fetches the time entries of `user_id`, or of every user when `None`.
     #[cfg_attr(feature = "trace", instrument)]
     pub async fn get_time_entries(
         &self,
-        user_id: u64,
-        from: Date,
-        to: Date,
+        user_id: Option<u64>,
+        filter: &TimeEntryFilter,
     ) -> Result<Vec<TimeEntry>> {
         #[derive(Deserialize, Debug)]
         struct BatchRequest {
@@ -77,38 +265,169 @@ impl Redmine {
         }
 
         let mut time_entries = Vec::new();
-        let mut offset = 0;
-        let mut total_count = usize::MAX;
+        let mut seen = HashSet::new();
 
-        let user_id = user_id.to_string();
-        let format = format_description!("[year]-[month]-[day]");
-        let from = from.format(&format)?;
-        let to = to.format(&format)?;
+        for project_id in filter.project_scopes() {
+            let mut fetched = 0;
+            let mut total_count = usize::MAX;
 
-        while time_entries.len() < total_count {
-            let time_entry_args = [("user_id", &user_id), ("from", &from), ("to", &to)];
+            let mut time_entry_args = filter.query(project_id);
+            if let Some(user_id) = user_id {
+                time_entry_args.push(("user_id", user_id.to_string()));
+            }
 
-            let mut res: BatchRequest = self
-                .get_api("time_entries", time_entry_args.into_iter(), offset)
-                .await
-                .with_context(|| format!("get time_entries {:?} failed", time_entry_args))?;
+            while fetched < total_count {
+                let res: BatchRequest = self
+                    .get_api("time_entries", time_entry_args.iter().cloned(), fetched)
+                    .await
+                    .with_context(|| format!("get time_entries {:?} failed", time_entry_args))?;
 
-            total_count = res.total_count;
-            offset += LIMIT;
+                metrics::redmine_page("time_entries");
+                total_count = res.total_count;
+                // Redmine may answer fewer entries than asked for
+                fetched += res.time_entries.len();
 
-            info!(
-                "Fetch time entries for user {}: {}/{}",
-                user_id,
-                time_entries.len(),
-                total_count
-            );
+                info!(
+                    "Fetch time entries for user {}: {}/{}",
+                    user_id.map_or_else(|| "*".to_string(), |user_id| user_id.to_string()),
+                    fetched,
+                    total_count
+                );
 
-            time_entries.append(&mut res.time_entries);
+                if res.time_entries.is_empty() {
+                    break;
+                }
+
+                time_entries.extend(
+                    res.time_entries
+                        .into_iter()
+                        // nested projects may return the same entry twice
+                        .filter(|time_entry| seen.insert(time_entry.id))
+                        .filter(|time_entry| filter.matches(time_entry))
+                        // saved queries take precedence over the user_id parameter
+                        .filter(|time_entry| user_id.is_none_or(|id| time_entry.user.id == id))
+                        .inspect(|time_entry| {
+                            trace!(
+                                "Time entry #{} of user {} on issue #{}: {}",
+                                time_entry.id,
+                                time_entry.user.id,
+                                time_entry.issue.id,
+                                Redacted(&time_entry.comments)
+                            )
+                        }),
+                );
+            }
         }
 
         Ok(time_entries)
     }
 
+    #[cfg_attr(feature = "trace", instrument)]
+    pub async fn get_user(&self, user_id: u64) -> Result<User> {
+        #[derive(Deserialize, Debug)]
+        struct UserRequest {
+            user: User,
+        }
+
+        let res: UserRequest = self
+            .get_api(
+                &format!("users/{}", user_id),
+                std::iter::empty::<(&str, &str)>(),
+                0,
+            )
+            .await
+            .with_context(|| format!("get user {} failed", user_id))?;
+
+        Ok(res.user)
+    }
+
+    #[cfg_attr(feature = "trace", instrument)]
+    pub async fn get_group(&self, group_id: u64) -> Result<Group> {
+        #[derive(Deserialize, Debug)]
+        struct GroupRequest {
+            group: Group,
+        }
+
+        let res: GroupRequest = self
+            .get_api(
+                &format!("groups/{}", group_id),
+                [("include", "users")].into_iter(),
+                0,
+            )
+            .await
+            .with_context(|| format!("get group {} failed", group_id))?;
+
+        Ok(res.group)
+    }
+
+    /// Looks a user up by exact login or email, which needs an administrator API key.
+    #[cfg_attr(feature = "trace", instrument)]
+    pub async fn find_user(&self, login: &str) -> Result<User> {
+        // `name` matches logins, names and emails by substring, so there may be many pages
+        let users: Vec<User> = self
+            .get_all("users", "users", &[("name", login)])
+            .await
+            .with_context(|| format!("find user {} failed", login))?;
+
+        users
+            .into_iter()
+            .find(|user| {
+                user.login == login
+                    || user
+                        .mail
+                        .as_ref()
+                        .is_some_and(|mail| mail.eq_ignore_ascii_case(login))
+            })
+            .ok_or_else(|| UnknownUser(login.to_string()).into())
+    }
+
+    /// Turns user ids, group ids and logins or emails into one set of users.
+    ///
+    /// Returns user ids in request order without duplicates, with the display names learned
+    /// along the way; users given by id only have no name yet.
+    #[cfg_attr(feature = "trace", instrument)]
+    pub async fn resolve_users(
+        &self,
+        user_ids: &[u64],
+        group_ids: &[u64],
+        logins: &[String],
+    ) -> Result<Vec<(u64, Option<String>)>> {
+        let (groups, users) = futures::future::try_join(
+            futures::future::try_join_all(group_ids.iter().map(|&id| self.get_group(id))),
+            futures::future::try_join_all(logins.iter().map(|login| self.find_user(login))),
+        )
+        .await?;
+
+        let mut names = HashMap::new();
+        let members = groups
+            .into_iter()
+            .flat_map(|group| {
+                debug!("Group {} has {} members", group.id, group.users.len());
+                group.users
+            })
+            .map(|user| {
+                names.insert(user.id, user.name);
+                user.id
+            })
+            .collect_vec();
+        let found = users
+            .into_iter()
+            .map(|user| {
+                names.insert(user.id, user.name());
+                user.id
+            })
+            .collect_vec();
+
+        Ok(user_ids
+            .iter()
+            .copied()
+            .chain(members)
+            .chain(found)
+            .unique()
+            .map(|user_id| (user_id, names.get(&user_id).cloned()))
+            .collect())
+    }
+
     #[cfg_attr(feature = "trace", instrument)]
     pub async fn get_issues(&self, issue_ids: Vec<u64>) -> Result<Vec<Issue>> {
         #[derive(Deserialize, Debug)]
@@ -116,8 +435,9 @@ impl Redmine {
             issues: Vec<Issue>,
         }
 
+        let page_size = self.page_size.min(MAX_PAGE_SIZE);
         let whole_issues =
-            futures::future::try_join_all(issue_ids.chunks(LIMIT).map(|issue_id| async {
+            futures::future::try_join_all(issue_ids.chunks(page_size).map(|issue_id| async {
                 let issue_ids = issue_id.iter().join(",");
 
                 let batch: BatchRequest = self
@@ -136,3 +456,105 @@ impl Redmine {
         Ok(whole_issues.into_iter().flatten().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use pretty_assertions::{assert_eq, assert_ne};
+    use serde_json::json;
+    use time::macros::date;
+
+    use super::*;
+    use crate::model::mock::MockRedmine;
+
+    /// Value of the query parameter `name` in a mock request.
+    fn param(request: &mock::Request, name: &str) -> usize {
+        reqwest::Url::parse(&format!("http://redmine/?{}", request.query))
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map_or(0, |(_, value)| value.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn find_user_past_first_page() {
+        // `alice` also matches the logins of her namesakes that come first
+        let logins = ["alice.b", "alice.c", "malice", "alice.d", "alice"];
+        let mock = MockRedmine::start(move |request| {
+            let users = logins
+                .iter()
+                .enumerate()
+                .skip(param(request, "offset"))
+                .take(param(request, "limit"))
+                .map(|(id, login)| json!({"id": id + 1, "login": login}))
+                .collect::<Vec<_>>();
+            (
+                StatusCode::OK,
+                json!({"users": users, "total_count": logins.len()}),
+            )
+        });
+        let redmine = mock.redmine(Some("admin")).with_page_size(2);
+
+        assert_eq!(redmine.find_user("alice").await.unwrap().id, 5);
+        assert_eq!(mock.requests().len(), 3);
+        assert!(redmine.find_user("bob").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn page_by_returned_entries() {
+        // Redmine answers at most 100 entries, whatever the limit
+        let mock = MockRedmine::start(|request| {
+            let time_entries = (param(request, "offset")..250)
+                .take(param(request, "limit").min(100))
+                .map(|id| {
+                    json!({
+                        "id": id,
+                        "hours": 1.0,
+                        "comments": "",
+                        "user": {"id": 1, "name": "User 1"},
+                        "issue": {"id": 1},
+                        "project": {"id": 1},
+                        "activity": {"id": 9},
+                        "spent_on": "2021-01-04",
+                    })
+                })
+                .collect::<Vec<_>>();
+            (
+                StatusCode::OK,
+                json!({"time_entries": time_entries, "total_count": 250}),
+            )
+        });
+        let redmine = mock.redmine(Some("admin")).with_page_size(250);
+        let filter = TimeEntryFilter::new(date!(2021 - 01 - 04), date!(2021 - 01 - 08));
+
+        let time_entries = redmine.get_time_entries(None, &filter).await.unwrap();
+        assert_eq!(time_entries.len(), 250);
+        assert_eq!(
+            mock.requests()
+                .iter()
+                .map(|request| param(request, "offset"))
+                .collect::<Vec<_>>(),
+            vec![0, 100, 200]
+        );
+    }
+
+    #[test]
+    fn cache_key_by_switched_user() {
+        let redmine = Redmine::new(
+            reqwest::Client::new(),
+            "https://redmine.example.com".parse().unwrap(),
+            Some(Secret::new("admin".to_string())),
+        );
+
+        let alice = redmine.switch_user("alice".to_string());
+        assert_ne!(redmine.cache_key(), alice.cache_key());
+        assert_ne!(
+            alice.cache_key(),
+            redmine.switch_user("bob".to_string()).cache_key()
+        );
+        assert_eq!(
+            alice.cache_key(),
+            redmine.switch_user("alice".to_string()).cache_key()
+        );
+    }
+}
@@ -3,6 +3,7 @@ use time::{macros::format_description, Date};
 
 pub mod time_entry {
     use super::*;
+    use crate::logging::Redacted;
 
     #[derive(Deserialize, Debug, Clone)]
     pub struct User {
@@ -15,7 +16,17 @@ pub mod time_entry {
         pub id: u64,
     }
 
-    #[derive(Deserialize, Debug)]
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Project {
+        pub id: u64,
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Activity {
+        pub id: u64,
+    }
+
+    #[derive(Deserialize)]
     pub struct TimeEntry {
         pub id: u64,
         pub hours: f64,
@@ -23,19 +34,116 @@ pub mod time_entry {
 
         pub user: User,
         pub issue: Issue,
+        pub project: Project,
+        pub activity: Activity,
 
         #[serde(deserialize_with = "super::deserialize_date")]
         pub spent_on: Date,
     }
+
+    // comments only reach the logs below the redaction level, never tracing spans
+    impl std::fmt::Debug for TimeEntry {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("TimeEntry")
+                .field("id", &self.id)
+                .field("hours", &self.hours)
+                .field("comments", &Redacted(&self.comments))
+                .field("user", &self.user)
+                .field("issue", &self.issue)
+                .field("project", &self.project)
+                .field("activity", &self.activity)
+                .field("spent_on", &self.spent_on)
+                .finish()
+        }
+    }
 }
 
 pub mod issue {
     use super::*;
 
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Tracker {
+        pub id: u64,
+    }
+
     #[derive(Deserialize, Debug)]
     pub struct Issue {
         pub id: u64,
         pub subject: String,
+        pub tracker: Tracker,
+    }
+}
+
+pub mod user {
+    use super::*;
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct User {
+        pub id: u64,
+        pub login: String,
+        #[serde(default)]
+        pub firstname: String,
+        #[serde(default)]
+        pub lastname: String,
+        /// Only visible to administrators and the user themselves.
+        #[serde(default)]
+        pub mail: Option<String>,
+    }
+
+    impl User {
+        /// Display name the way Redmine shows it by default.
+        pub fn name(&self) -> String {
+            format!("{} {}", self.firstname, self.lastname)
+                .trim()
+                .to_string()
+        }
+    }
+}
+
+pub mod project {
+    use super::*;
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Parent {
+        pub id: u64,
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Project {
+        pub id: u64,
+        pub name: String,
+        pub identifier: String,
+        #[serde(default)]
+        pub parent: Option<Parent>,
+    }
+}
+
+pub mod activity {
+    use super::*;
+
+    fn active() -> bool {
+        true
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Activity {
+        pub id: u64,
+        pub name: String,
+        /// Missing before Redmine 3.4, where every listed activity is active.
+        #[serde(default = "active")]
+        pub active: bool,
+    }
+}
+
+pub mod group {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Group {
+        pub id: u64,
+        /// Only present with `include=users`.
+        #[serde(default)]
+        pub users: Vec<time_entry::User>,
     }
 }
 
@@ -0,0 +1,155 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{ensure, Context, Result};
+use log::{error, info};
+
+/// How often secret files are checked for modifications.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+pub const MASK: &str = "********";
+
+/// API key or token which is never printed, `Debug` shows a mask instead.
+///
+/// Clones share the value, so a [`Secret::watch`] rotation reaches all of them.
+#[derive(Clone)]
+pub struct Secret {
+    value: Arc<RwLock<String>>,
+    /// Modification time of the file it was read from, taken before the read.
+    modified: Option<SystemTime>,
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self {
+            value: Arc::new(RwLock::new(value)),
+            modified: None,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        // a change while the file is read is picked up by the watch
+        let modified = modified(path);
+
+        Ok(Self {
+            modified,
+            ..Self::new(read(path)?)
+        })
+    }
+
+    pub fn expose(&self) -> String {
+        self.value.read().unwrap().clone()
+    }
+
+    /// Replaces the value with the content of `path` whenever the file changes after it was
+    /// read by [`Secret::from_file`].
+    pub fn watch(&self, path: PathBuf) {
+        self.watch_with(path, |_| Ok(()));
+    }
+
+    /// Like [`Secret::watch`], passing the new content to `reload` first, which may reject it.
+    pub fn watch_with<F>(&self, path: PathBuf, mut reload: F)
+    where
+        F: FnMut(&str) -> Result<()> + Send + 'static,
+    {
+        let value = self.value.clone();
+
+        on_change(path, self.modified, move |content| {
+            reload(&content)?;
+            *value.write().unwrap() = content;
+            Ok(())
+        });
+    }
+}
+
+/// Reads a secret file, surrounding whitespace like the trailing newline is dropped.
+pub fn read(path: &Path) -> Result<String> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read {}", path.display()))?;
+    let content = content.trim();
+    ensure!(!content.is_empty(), "{} is empty", path.display());
+
+    Ok(content.to_string())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|meta| meta.modified()).ok()
+}
+
+/// Passes the new content of `path` to `reload` whenever the file is modified, starting
+/// from its modification time `since`.
+///
+/// Failed reads and rejected contents are logged, the previous value stays in use.
+fn on_change<F>(path: PathBuf, since: Option<SystemTime>, mut reload: F)
+where
+    F: FnMut(String) -> Result<()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut last = since;
+
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+
+            let current = modified(&path);
+            if current == last {
+                continue;
+            }
+            last = current;
+
+            match read(&path).and_then(&mut reload) {
+                Ok(()) => info!("Reloaded secret {}", path.display()),
+                Err(err) => error!(
+                    "Reload of secret {} failed, keep the previous value: {:#}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn mask_secret() {
+        let secret = Secret::new("0123456789abcdef".to_string());
+
+        assert_eq!(format!("{:?}", Some(secret.clone())), "Some(********)");
+        assert_eq!(secret.expose(), "0123456789abcdef");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reload_changes_since_read() {
+        let path = std::env::temp_dir().join(format!("secret-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let secret = Secret::from_file(&path).unwrap();
+
+        // rotated before the watch started
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(&path, "second\n").unwrap();
+        secret.watch_with(path.clone(), |content| {
+            ensure!(content != "rejected", "rejected");
+            Ok(())
+        });
+        tokio::time::sleep(RELOAD_INTERVAL + Duration::from_secs(1)).await;
+        assert_eq!(secret.expose(), "second");
+
+        std::fs::write(&path, "rejected\n").unwrap();
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        assert_eq!(secret.expose(), "second");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::info;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+pub const DEFAULT_DRAIN_DELAY: Duration = Duration::from_secs(5);
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Shutdown request, triggered by SIGTERM or SIGINT and observed by every server.
+///
+/// Health checks fail as soon as it is requested, the servers keep accepting requests for
+/// the drain delay so load balancers notice first.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+    drained: watch::Receiver<bool>,
+}
+
+async fn wait(mut flag: watch::Receiver<bool>) {
+    while !*flag.borrow() {
+        if flag.changed().await.is_err() {
+            // the listener is gone, so no shutdown will ever be requested
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Shutdown {
+    /// Starts listening for SIGTERM and SIGINT.
+    pub fn on_signals(drain_delay: Duration) -> Result<Self> {
+        let mut terminate = signal(SignalKind::terminate())
+            .with_context(|| "unable to listen for SIGTERM".to_string())?;
+        let mut interrupt = signal(SignalKind::interrupt())
+            .with_context(|| "unable to listen for SIGINT".to_string())?;
+        let (request, requested) = watch::channel(false);
+        let (drain, drained) = watch::channel(false);
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = terminate.recv() => info!("Got SIGTERM, shutting down"),
+                _ = interrupt.recv() => info!("Got SIGINT, shutting down"),
+            }
+            request.send(true).ok();
+
+            info!("Stop accepting requests in {:?}", drain_delay);
+            tokio::time::sleep(drain_delay).await;
+            drain.send(true).ok();
+        });
+
+        Ok(Self { requested, drained })
+    }
+
+    /// Resolves once shutdown was requested, the time to fail health checks.
+    pub async fn requested(&self) {
+        wait(self.requested.clone()).await
+    }
+
+    /// Resolves once the drain delay after the request passed, the time to stop accepting.
+    pub async fn drained(&self) {
+        wait(self.drained.clone()).await
+    }
+}
@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use axum::http::{self, HeaderMap, HeaderValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    sdk::{
+        propagation::TraceContextPropagator,
+        resource::{EnvResourceDetector, SdkProvidedResourceDetector},
+        trace::{self, Sampler},
+        Resource,
+    },
+    Key, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// `service.name` unless `OTEL_SERVICE_NAME` or `OTEL_RESOURCE_ATTRIBUTES` set one.
+const SERVICE_NAME: &str = "redmine-service";
+const UNKNOWN_SERVICE: &str = "unknown_service";
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            http::header::HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// Sampler from `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`, parent based by default.
+fn sampler(name: Option<&str>, arg: Option<&str>) -> Result<Sampler> {
+    let ratio = || -> Result<f64> {
+        match arg {
+            Some(arg) => arg
+                .parse()
+                .with_context(|| "env OTEL_TRACES_SAMPLER_ARG must be a ratio".to_string()),
+            None => Ok(1.0),
+        }
+    };
+    let parent_based = |root| Sampler::ParentBased(Box::new(root));
+
+    Ok(match name.unwrap_or("parentbased_always_on") {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()?),
+        "parentbased_always_on" => parent_based(Sampler::AlwaysOn),
+        "parentbased_always_off" => parent_based(Sampler::AlwaysOff),
+        "parentbased_traceidratio" => parent_based(Sampler::TraceIdRatioBased(ratio()?)),
+        sampler => bail!("env OTEL_TRACES_SAMPLER: unsupported sampler {}", sampler),
+    })
+}
+
+fn resource() -> Resource {
+    let detected = Resource::from_detectors(
+        Duration::ZERO,
+        vec![
+            Box::new(SdkProvidedResourceDetector),
+            Box::new(EnvResourceDetector::new()),
+        ],
+    );
+
+    match detected.get(Key::new("service.name")) {
+        Some(name) if name.as_str() != UNKNOWN_SERVICE => detected,
+        _ => detected.merge(&Resource::new([KeyValue::new(
+            "service.name",
+            SERVICE_NAME,
+        )])),
+    }
+}
+
+/// Installs the OTLP batch exporter and the W3C trace context propagator.
+///
+/// The exporter follows the standard `OTEL_EXPORTER_OTLP_*` and `OTEL_BSP_*` env vars.
+pub fn init() -> Result<()> {
+    use tracing_subscriber::{fmt, layer::SubscriberExt};
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler(
+                    std::env::var("OTEL_TRACES_SAMPLER").ok().as_deref(),
+                    std::env::var("OTEL_TRACES_SAMPLER_ARG").ok().as_deref(),
+                )?)
+                .with_resource(resource()),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+        .with_context(|| "OTLP exporter was not installed".to_string())?;
+    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+
+    tracing::subscriber::set_global_default(
+        fmt::Subscriber::builder()
+            // subscriber configuration
+            .with_max_level(tracing::Level::INFO)
+            .finish()
+            // add additional writers
+            .with(telemetry)
+            .with(fmt::Layer::default()),
+    )
+    .with_context(|| "Unable to set global tracing subscriber".to_string())?;
+    log::debug!("Tracing initialized.");
+
+    Ok(())
+}
+
+/// Exports the spans still queued in the batch.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Server span of an HTTP request, continuing the trace of its `traceparent` header if any.
+pub fn http_span(name: &str, headers: &HeaderMap) -> Span {
+    let span = tracing::info_span!("request", otel.name = name, otel.kind = "server");
+    span.set_parent(global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    }));
+    span
+}
+
+/// Span of a gRPC call, see `Server::trace_fn`.
+pub fn grpc_span(request: &http::Request<()>) -> Span {
+    http_span(
+        request.uri().path().trim_start_matches('/'),
+        request.headers(),
+    )
+}
+
+/// Adds the trace context of the current span to outgoing request headers.
+pub fn inject(headers: &mut HeaderMap) {
+    let context = Span::current().context();
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_sampler() {
+        assert_eq!(
+            format!("{:?}", sampler(None, None).unwrap()),
+            "ParentBased(AlwaysOn)"
+        );
+        assert_eq!(
+            format!("{:?}", sampler(Some("traceidratio"), Some("0.25")).unwrap()),
+            "TraceIdRatioBased(0.25)"
+        );
+        assert!(sampler(Some("traceidratio"), Some("most")).is_err());
+        assert!(sampler(Some("jaeger_remote"), None).is_err());
+    }
+
+    #[test]
+    fn propagate_trace_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", HeaderValue::from_static(traceparent));
+        let context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(&headers))
+        });
+
+        let mut outgoing = HeaderMap::new();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(&mut outgoing))
+        });
+        assert_eq!(outgoing["traceparent"], traceparent);
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    io::{self, Cursor},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use futures::Stream;
+use log::{debug, error, info};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile, AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey, RootCertStore,
+        ServerConfig,
+    },
+    server::TlsStream,
+};
+
+/// How often certificate files are checked for modifications.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// Time a client gets to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshaken connections waiting for the server to pick them up.
+const ACCEPTED_BACKLOG: usize = 64;
+/// gRPC needs HTTP/2, tonic's own TLS setup only offers it as well.
+const ALPN_H2: &[u8] = b"h2";
+
+/// PEM files used to terminate TLS on the gRPC server.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CA used to verify client certificates, enforcing mutual TLS when set.
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Server configuration read by [`TlsFiles::load`].
+struct Certificates {
+    config: Arc<ServerConfig>,
+    /// Modification times of the files when they were read.
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl TlsFiles {
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.cert, &self.key].into_iter().chain(&self.client_ca)
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths()
+            .map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+            .collect()
+    }
+
+    /// Reads the files and checks that they make up a usable server configuration.
+    fn load(&self) -> Result<Certificates> {
+        // taken first, so a change while the files are read is picked up by the next check
+        let modified = self.modified();
+        let read = |path: &PathBuf| {
+            std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))
+        };
+        let invalid = |path: &PathBuf| format!("invalid PEM file {}", path.display());
+
+        let certs = pemfile::certs(&mut Cursor::new(read(&self.cert)?))
+            .ok()
+            .filter(|certs| !certs.is_empty())
+            .with_context(|| invalid(&self.cert))?;
+        let key = private_key(&read(&self.key)?).with_context(|| invalid(&self.key))?;
+
+        let mut config = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                match roots.add_pem_file(&mut Cursor::new(read(client_ca)?)) {
+                    Ok((valid, _)) if valid > 0 => {}
+                    _ => bail!(invalid(client_ca)),
+                }
+                ServerConfig::new(AllowAnyAuthenticatedClient::new(roots))
+            }
+            None => ServerConfig::new(NoClientAuth::new()),
+        };
+        config
+            .set_single_cert(certs, key)
+            .with_context(|| "invalid TLS certificate or key".to_string())?;
+        config.set_protocols(&[ALPN_H2.to_vec()]);
+
+        Ok(Certificates {
+            config: Arc::new(config),
+            modified,
+        })
+    }
+}
+
+/// The first PKCS #8 or else RSA key, like tonic's own TLS setup.
+fn private_key(pem: &[u8]) -> Result<PrivateKey> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut Cursor::new(pem)).unwrap_or_default();
+    let rsa = || pemfile::rsa_private_keys(&mut Cursor::new(pem)).unwrap_or_default();
+
+    pkcs8
+        .into_iter()
+        .next()
+        .or_else(|| rsa().into_iter().next())
+        .with_context(|| "no PKCS #8 or RSA private key found".to_string())
+}
+
+/// Terminates TLS in front of the gRPC server, with certificates that are replaced on
+/// modification while the listener stays open.
+#[derive(Clone)]
+pub struct Acceptor {
+    files: TlsFiles,
+    certificates: Arc<RwLock<Certificates>>,
+}
+
+impl Acceptor {
+    pub fn new(files: TlsFiles) -> Result<Self> {
+        let certificates = files.load()?;
+
+        Ok(Self {
+            files,
+            certificates: Arc::new(RwLock::new(certificates)),
+        })
+    }
+
+    /// Loads the files if they changed since the last load, keeping the previous
+    /// certificates when they are not usable.
+    fn reload(&self) {
+        let modified = self.files.modified();
+        if modified == self.certificates.read().unwrap().modified {
+            return;
+        }
+        debug!("TLS files {:?} changed", self.files);
+
+        match self.files.load() {
+            Ok(certificates) => {
+                info!("TLS certificates reloaded");
+                *self.certificates.write().unwrap() = certificates;
+            }
+            Err(err) => {
+                error!(
+                    "TLS reload failed, keep the previous certificates: {:#}",
+                    err
+                );
+                self.certificates.write().unwrap().modified = modified;
+            }
+        }
+    }
+
+    /// Checks the files for modifications for as long as the acceptor is used.
+    pub async fn watch(self) {
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+            if Arc::strong_count(&self.certificates) == 1 {
+                return;
+            }
+            self.reload();
+        }
+    }
+
+    /// Connections of `listener` once their handshake, with the certificates current at
+    /// the time, completed.
+    pub fn incoming(
+        &self,
+        listener: TcpListener,
+    ) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+        let (sender, receiver) = mpsc::channel(ACCEPTED_BACKLOG);
+
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    // the server is gone
+                    _ = sender.closed() => return,
+                };
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!("Accepting a connection failed: {}", err);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+
+                let tls = tokio_rustls::TlsAcceptor::from(
+                    acceptor.certificates.read().unwrap().config.clone(),
+                );
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(stream).await;
+                        }
+                        Ok(Err(err)) => debug!("TLS handshake with {} failed: {}", peer, err),
+                        Err(_) => debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            let stream = receiver.recv().await?;
+            Some((Ok(stream), receiver))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use pretty_assertions::{assert_eq, assert_ne};
+    use tokio_rustls::{
+        rustls::{Certificate, ClientConfig, Session},
+        webpki::DNSNameRef,
+        TlsConnector,
+    };
+
+    use super::*;
+
+    const FIRST_CERT: &str = include_str!("../testdata/tls/first.crt");
+    const FIRST_KEY: &str = include_str!("../testdata/tls/first.key");
+    const SECOND_CERT: &str = include_str!("../testdata/tls/second.crt");
+    const SECOND_KEY: &str = include_str!("../testdata/tls/second.key");
+
+    fn der(pem: &str) -> Certificate {
+        pemfile::certs(&mut Cursor::new(pem)).unwrap().remove(0)
+    }
+
+    /// Certificate the server presents to a client trusting both test certificates.
+    async fn served_cert(addr: std::net::SocketAddr) -> Certificate {
+        let mut config = ClientConfig::new();
+        for cert in [FIRST_CERT, SECOND_CERT] {
+            config.root_store.add(&der(cert)).unwrap();
+        }
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        stream
+            .get_ref()
+            .1
+            .get_peer_certificates()
+            .unwrap()
+            .remove(0)
+    }
+
+    #[tokio::test]
+    async fn reload_behind_listener() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = TlsFiles {
+            cert: dir.join("server.crt"),
+            key: dir.join("server.key"),
+            client_ca: None,
+        };
+        let write = |cert: &str, key: &str| {
+            std::fs::write(&files.cert, cert).unwrap();
+            std::fs::write(&files.key, key).unwrap();
+        };
+        write(FIRST_CERT, FIRST_KEY);
+
+        let acceptor = Acceptor::new(files.clone()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut incoming = Box::pin(acceptor.incoming(listener));
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Some(connection) = incoming.next().await {
+                connections.push(connection.unwrap());
+            }
+        });
+        assert_eq!(served_cert(addr).await, der(FIRST_CERT));
+
+        // files modified after the load are noticed even before the first check
+        let loaded = acceptor.certificates.read().unwrap().modified.clone();
+        std::thread::sleep(Duration::from_millis(10));
+        write(SECOND_CERT, SECOND_KEY);
+        assert_ne!(files.modified(), loaded);
+
+        acceptor.reload();
+        assert_eq!(served_cert(addr).await, der(SECOND_CERT));
+
+        // unusable files keep the previous certificates
+        write("", SECOND_KEY);
+        acceptor.reload();
+        assert_eq!(served_cert(addr).await, der(SECOND_CERT));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
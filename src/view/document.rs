@@ -0,0 +1,97 @@
+use std::fmt::Write;
+
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+/// Joins per-user markdown reports into a single document with a section per user.
+///
+/// Sections are titled with the user name, if known, and id.
+pub fn markdown<'a>(reports: impl IntoIterator<Item = (u64, &'a str, &'a str)>) -> String {
+    reports.into_iter().fold(
+        String::new(),
+        |mut document, (user_id, user_name, report)| {
+            match user_name {
+                "" => write!(&mut document, "## User #{}\n\n{}\n", user_id, report),
+                name => write!(&mut document, "## {} (#{})\n\n{}\n", name, user_id, report),
+            }
+            .unwrap();
+            document
+        },
+    )
+}
+
+/// Schemes links and images may point to, anything else could run script.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Whether `url` is absolute with a safe scheme, parsed the way browsers do.
+fn is_safe_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| SAFE_SCHEMES.contains(&url.scheme()))
+}
+
+/// Renders `markdown` as HTML. Comments come from any Redmine user, so raw HTML in it is
+/// escaped and links or images to unsafe URLs are reduced to their text.
+pub fn html(markdown: &str) -> String {
+    let events = Parser::new(markdown).filter_map(|event| match event {
+        Event::Html(html) => Some(Event::Text(html)),
+        Event::Start(Tag::Link(_, url, _) | Tag::Image(_, url, _))
+        | Event::End(Tag::Link(_, url, _) | Tag::Image(_, url, _))
+            if !is_safe_url(&url) =>
+        {
+            None
+        }
+        event => Some(event),
+    });
+
+    let mut document = String::new();
+    html::push_html(&mut document, events);
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn escape_raw_html() {
+        let report = indoc! {r#"
+            * **#1: Issue 1**
+
+              Deployed <script>alert("xss")</script>  
+
+            <img src=x onerror=alert(1)>
+        "#};
+
+        assert_eq!(
+            html(report),
+            indoc! {r#"
+                <ul>
+                <li>
+                <p><strong>#1: Issue 1</strong></p>
+                <p>Deployed &lt;script&gt;alert(&quot;xss&quot;)&lt;/script&gt;</p>
+                </li>
+                </ul>
+                &lt;img src=x onerror=alert(1)&gt;
+            "#}
+        );
+    }
+
+    #[test]
+    fn drop_unsafe_urls() {
+        let report = indoc! {r#"
+            [docs](https://redmine.example.com/issues/1) [x](javascript:alert(1))
+            [mail](mailto:dev@example.com) ![pic](JaVaScRiPt:alert(2)) [rel](/issues/1)
+            <javascript:alert(3)> ![logo](data:image/svg+xml,<svg/onload=alert(4)>)
+        "#};
+
+        assert_eq!(
+            html(report),
+            indoc! {r#"
+                <p><a href="https://redmine.example.com/issues/1">docs</a> x
+                <a href="mailto:dev@example.com">mail</a> pic rel
+                javascript:alert(3) logo</p>
+            "#}
+        );
+    }
+}
@@ -1,24 +1,112 @@
-use std::collections::{HashMap, HashSet};
-
-use time::Date;
-use tonic::Status;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+};
+
+use itertools::Itertools;
+use log::debug;
+use tonic::{Code, Status};
 #[cfg(feature = "trace")]
 use tracing::instrument;
 
-use crate::model::{self as redmine, Redmine};
+use crate::{
+    logging::Redacted,
+    model::{self as redmine, Redmine, TimeEntryFilter},
+};
 
 pub struct Report {
     pub user_id: u64,
+    /// Empty when the user has no time entries to take it from.
+    pub user_name: String,
     pub report: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 struct Issue {
     id: u64,
     hours: f64,
     comments: String,
 }
 
+impl std::fmt::Debug for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Issue")
+            .field("id", &self.id)
+            .field("hours", &self.hours)
+            .field("comments", &Redacted(&self.comments))
+            .finish()
+    }
+}
+
+/// Maps a failed Redmine call to a gRPC status, keeping Redmine's own access decisions.
+pub(crate) fn redmine_error(context: &str, err: anyhow::Error) -> Status {
+    let message = format!("{}: {}", context, err);
+
+    if err.downcast_ref::<redmine::UnknownUser>().is_some() {
+        return Status::not_found(message);
+    }
+    if err.downcast_ref::<redmine::DeadlineExceeded>().is_some() {
+        return Status::deadline_exceeded(message);
+    }
+
+    match err
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+    {
+        Some(reqwest::StatusCode::UNAUTHORIZED) => Status::unauthenticated(message),
+        Some(reqwest::StatusCode::FORBIDDEN) => Status::permission_denied(message),
+        Some(reqwest::StatusCode::NOT_FOUND) => Status::not_found(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn unfetched(user_ids: &[u64]) -> Status {
+    Status::deadline_exceeded(format!(
+        "deadline exceeded, time entries of users {} were not fetched yet",
+        user_ids.iter().join(", ")
+    ))
+}
+
+/// Runs `fetch` for every user and stops at the first error, except for a passed deadline:
+/// then the other users are still waited for, so it can be reported with every user left
+/// without time entries.
+async fn for_each_user<T, F, Fut>(user_ids: &[u64], fetch: F) -> Result<Vec<T>, Status>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<T, Status>>,
+{
+    use futures::stream::{FuturesUnordered, StreamExt};
+
+    let mut pending = user_ids
+        .iter()
+        .enumerate()
+        .map(|(index, &user_id)| {
+            let fetched = fetch(user_id);
+            async move { (index, user_id, fetched.await) }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut fetched = Vec::new();
+    let mut late = Vec::new();
+    while let Some((index, user_id, result)) = pending.next().await {
+        match result {
+            Ok(value) => fetched.push((index, value)),
+            Err(status) if status.code() == Code::DeadlineExceeded => late.push((index, user_id)),
+            // dropping the other fetches cancels them
+            Err(status) => return Err(status),
+        }
+    }
+
+    if !late.is_empty() {
+        late.sort_unstable();
+        return Err(unfetched(
+            &late.into_iter().map(|(_, user_id)| user_id).collect_vec(),
+        ));
+    }
+    fetched.sort_by_key(|(index, _)| *index);
+    Ok(fetched.into_iter().map(|(_, value)| value).collect())
+}
+
 #[cfg_attr(feature = "trace", instrument)]
 fn process_time_entries(time_entries: Vec<redmine::TimeEntry>) -> Vec<Issue> {
     type IssueID = u64;
@@ -76,13 +164,22 @@ fn generate_report_by_user(
         .fold(String::new(), |mut report, issue| {
             use std::fmt::Write;
 
-            write!(
-                &mut report,
-                "* **#{issue_id}: {subject}**\n\n{comments}\n",
-                issue_id = issue.id,
-                subject = issues[&issue.id].subject.trim(),
-                comments = issue.comments
-            )
+            // issues Redmine did not answer, e.g. deleted since, are listed without subject
+            match issues.get(&issue.id) {
+                Some(redmine_issue) => write!(
+                    &mut report,
+                    "* **#{issue_id}: {subject}**\n\n{comments}\n",
+                    issue_id = issue.id,
+                    subject = redmine_issue.subject.trim(),
+                    comments = issue.comments
+                ),
+                None => write!(
+                    &mut report,
+                    "* **#{issue_id}**\n\n{comments}\n",
+                    issue_id = issue.id,
+                    comments = issue.comments
+                ),
+            }
             .unwrap();
 
             report
@@ -92,8 +189,8 @@ fn generate_report_by_user(
 #[cfg_attr(feature = "trace", instrument)]
 fn extract_issues_from_time_entries(time_entries: &HashMap<u64, Vec<Issue>>) -> Vec<u64> {
     time_entries
-        .iter()
-        .flat_map(|(_, issues)| issues.iter())
+        .values()
+        .flat_map(|issues| issues.iter())
         .map(|issue| issue.id)
         .collect::<HashSet<u64>>()
         .into_iter()
@@ -108,44 +205,153 @@ async fn fetch_issues(
     Ok(redmine
         .get_issues(issues)
         .await
-        .map_err(|err| Status::internal(format!("get issues: {}", err)))?
+        .map_err(|err| redmine_error("get issues", err))?
         .into_iter()
         .map(|issue| (issue.id, issue))
         .collect())
 }
 
+/// Resolves groups and logins or emails to users, see [`Redmine::resolve_users`].
+#[cfg_attr(feature = "trace", instrument)]
+pub async fn resolve_users(
+    redmine: &Redmine,
+    user_ids: &[u64],
+    group_ids: &[u64],
+    logins: &[String],
+) -> Result<Vec<(u64, Option<String>)>, Status> {
+    redmine
+        .resolve_users(user_ids, group_ids, logins)
+        .await
+        .map_err(|err| redmine_error("resolve users", err))
+}
+
+#[cfg_attr(feature = "trace", instrument)]
+async fn impersonate(redmine: &Redmine, user_id: u64) -> Result<Redmine, Status> {
+    let user = redmine
+        .get_user(user_id)
+        .await
+        .map_err(|err| redmine_error("get user", err))?;
+
+    debug!("Impersonate user {} as {}", user.id, user.login);
+    Ok(redmine.switch_user(user.login))
+}
+
+/// Fetches a saved query once and splits its results by user.
+///
+/// Without `user_ids` every user found in the results gets a report.
+#[cfg_attr(feature = "trace", instrument)]
+async fn fetch_query_time_entries(
+    redmine: &Redmine,
+    user_ids: &[u64],
+    filter: &TimeEntryFilter,
+) -> Result<Vec<(u64, Vec<redmine::TimeEntry>)>, Status> {
+    let mut by_user: HashMap<u64, Vec<redmine::TimeEntry>> = user_ids
+        .iter()
+        .map(|&user_id| (user_id, Vec::new()))
+        .collect();
+
+    let time_entries = redmine
+        .get_time_entries(None, filter)
+        .await
+        .map_err(|err| match redmine_error("get time_entries", err) {
+            status if status.code() == Code::DeadlineExceeded && !user_ids.is_empty() => {
+                unfetched(user_ids)
+            }
+            status => status,
+        })?;
+
+    for time_entry in time_entries {
+        if user_ids.is_empty() {
+            by_user
+                .entry(time_entry.user.id)
+                .or_default()
+                .push(time_entry);
+        } else if let Some(user_time_entries) = by_user.get_mut(&time_entry.user.id) {
+            user_time_entries.push(time_entry);
+        }
+    }
+
+    Ok(by_user.into_iter().collect())
+}
+
+/// Builds one report per user.
+///
+/// With `impersonate` every report is fetched as its own user, so it contains only what
+/// that user can see in Redmine.
 #[cfg_attr(feature = "trace", instrument)]
 pub async fn aggregate_report(
     redmine: &Redmine,
     user_ids: &[u64],
-    from: Date,
-    to: Date,
+    filter: &TimeEntryFilter,
+    impersonate: bool,
 ) -> Result<Vec<Report>, Status> {
-    let mut time_entries = HashMap::new();
+    if !impersonate {
+        return collect_reports(redmine, user_ids, filter).await;
+    }
 
-    let collected = futures::future::try_join_all(user_ids.iter().map(|&user_id| async move {
-        let time_entries = redmine
-            .get_time_entries(user_id, from, to)
-            .await
-            .map_err(|err| Status::internal(format!("get time_entries: {}", err)))?;
+    let reports = for_each_user(user_ids, |user_id| async move {
+        let redmine = self::impersonate(redmine, user_id).await?;
 
-        Ok::<(u64, Vec<redmine::TimeEntry>), Status>((user_id, time_entries))
-    }))
+        collect_reports(&redmine, &[user_id], filter).await
+    })
     .await?;
 
+    Ok(reports.into_iter().flatten().collect())
+}
+
+#[cfg_attr(feature = "trace", instrument)]
+async fn collect_reports(
+    redmine: &Redmine,
+    user_ids: &[u64],
+    filter: &TimeEntryFilter,
+) -> Result<Vec<Report>, Status> {
+    let mut time_entries = HashMap::new();
+
+    let collected = if filter.query_id.is_some() {
+        fetch_query_time_entries(redmine, user_ids, filter).await?
+    } else {
+        for_each_user(user_ids, |user_id| async move {
+            let time_entries = redmine
+                .get_time_entries(Some(user_id), filter)
+                .await
+                .map_err(|err| redmine_error("get time_entries", err))?;
+
+            Ok::<(u64, Vec<redmine::TimeEntry>), Status>((user_id, time_entries))
+        })
+        .await?
+    };
+
+    let mut user_names = HashMap::new();
     for (user_id, time_entry) in collected {
+        if let Some(time_entry) = time_entry.first() {
+            user_names.insert(user_id, time_entry.user.name.clone());
+        }
         time_entries.insert(user_id, process_time_entries(time_entry));
     }
 
     let issues = extract_issues_from_time_entries(&time_entries);
     let issues = fetch_issues(redmine, issues).await?;
 
+    if !filter.tracker_ids.is_empty() {
+        for user_issues in time_entries.values_mut() {
+            user_issues.retain(|issue| {
+                issues
+                    .get(&issue.id)
+                    .is_some_and(|issue| filter.tracker_ids.contains(&issue.tracker.id))
+            });
+        }
+    }
+
     Ok(time_entries
         .into_iter()
         .map(|(user_id, time_entry)| {
             let report = generate_report_by_user(time_entry, &issues);
 
-            Report { user_id, report }
+            Report {
+                user_id,
+                user_name: user_names.remove(&user_id).unwrap_or_default(),
+                report,
+            }
         })
         .collect())
 }
@@ -200,7 +406,7 @@ mod tests {
 
     fn get_raw_time_entries() -> Vec<redmine::TimeEntry> {
         use redmine::{
-            types::time_entry::{Issue, User},
+            types::time_entry::{Activity, Issue, Project, User},
             TimeEntry,
         };
 
@@ -217,6 +423,8 @@ mod tests {
         let issue_4 = Issue { id: 4 };
         let issue_5 = Issue { id: 5 };
         let issue_6 = Issue { id: 6 };
+        let project = Project { id: 1 };
+        let activity = Activity { id: 1 };
         vec![
             TimeEntry {
                 id: 5,
@@ -224,6 +432,8 @@ mod tests {
                 comments: "Note 5".to_string(),
                 user: user_1.clone(),
                 issue: issue_1.clone(),
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: today,
             },
             TimeEntry {
@@ -232,6 +442,8 @@ mod tests {
                 comments: "Note 4".to_string(),
                 user: user_1.clone(),
                 issue: issue_1,
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: today,
             },
             TimeEntry {
@@ -240,6 +452,8 @@ mod tests {
                 comments: "Note 3".to_string(),
                 user: user_1.clone(),
                 issue: issue_2,
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: yesterday,
             },
             TimeEntry {
@@ -248,6 +462,8 @@ mod tests {
                 comments: "Note 2".to_string(),
                 user: user_1.clone(),
                 issue: issue_4.clone(),
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: yesterday,
             },
             TimeEntry {
@@ -256,6 +472,8 @@ mod tests {
                 comments: "Note 1".to_string(),
                 user: user_1.clone(),
                 issue: issue_4,
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: today,
             },
             TimeEntry {
@@ -264,6 +482,8 @@ mod tests {
                 comments: "Note 8".to_string(),
                 user: user_1.clone(),
                 issue: issue_3,
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: today,
             },
             TimeEntry {
@@ -272,6 +492,8 @@ mod tests {
                 comments: "Note 9".to_string(),
                 user: user_1.clone(),
                 issue: issue_5,
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: today,
             },
             TimeEntry {
@@ -280,11 +502,61 @@ mod tests {
                 comments: "Note 10".to_string(),
                 user: user_1,
                 issue: issue_6,
+                project: project.clone(),
+                activity: activity.clone(),
                 spent_on: today,
             },
         ]
     }
 
+    #[tokio::test]
+    async fn report_unfetched_users() {
+        let fetch = |user_id| async move {
+            match user_id {
+                1 => Ok(user_id),
+                2 => Err(Status::internal("boom")),
+                _ => Err(Status::deadline_exceeded("late")),
+            }
+        };
+
+        assert_eq!(for_each_user(&[1], fetch).await.unwrap(), vec![1]);
+        assert_eq!(
+            for_each_user(&[1, 3, 4], fetch)
+                .await
+                .unwrap_err()
+                .message(),
+            "deadline exceeded, time entries of users 3, 4 were not fetched yet"
+        );
+        assert_eq!(
+            for_each_user(&[3, 2], fetch).await.unwrap_err().code(),
+            Code::Internal
+        );
+        assert_eq!(
+            for_each_user(
+                &[4, 1, 3],
+                |user_id| async move { Ok::<_, Status>(user_id) }
+            )
+            .await
+            .unwrap(),
+            vec![4, 1, 3]
+        );
+
+        // other users are not waited for after an error
+        let stuck = |user_id| async move {
+            match user_id {
+                2 => Err(Status::internal("boom")),
+                _ => futures::future::pending().await,
+            }
+        };
+        assert_eq!(
+            for_each_user::<u64, _, _>(&[1, 2], stuck)
+                .await
+                .unwrap_err()
+                .code(),
+            Code::Internal
+        );
+    }
+
     #[test]
     fn test_extract_issues() {
         let mut per_user_time_entries = HashMap::new();
@@ -375,6 +647,7 @@ mod tests {
                 redmine::Issue {
                     id: 1,
                     subject: "Issue 1".to_string(),
+                    tracker: redmine::types::issue::Tracker { id: 1 },
                 },
             ),
             (
@@ -382,6 +655,7 @@ mod tests {
                 redmine::Issue {
                     id: 2,
                     subject: "Issue 2".to_string(),
+                    tracker: redmine::types::issue::Tracker { id: 1 },
                 },
             ),
             (
@@ -389,6 +663,7 @@ mod tests {
                 redmine::Issue {
                     id: 3,
                     subject: "Issue 3".to_string(),
+                    tracker: redmine::types::issue::Tracker { id: 1 },
                 },
             ),
             (
@@ -396,6 +671,7 @@ mod tests {
                 redmine::Issue {
                     id: 4,
                     subject: "Issue 4".to_string(),
+                    tracker: redmine::types::issue::Tracker { id: 1 },
                 },
             ),
             (
@@ -403,6 +679,7 @@ mod tests {
                 redmine::Issue {
                     id: 5,
                     subject: "Issue 5".to_string(),
+                    tracker: redmine::types::issue::Tracker { id: 1 },
                 },
             ),
             (
@@ -410,6 +687,7 @@ mod tests {
                 redmine::Issue {
                     id: 6,
                     subject: "Issue 6".to_string(),
+                    tracker: redmine::types::issue::Tracker { id: 1 },
                 },
             ),
         ]
@@ -449,4 +727,118 @@ mod tests {
 
         assert_eq!(expected, report);
     }
+
+    #[test]
+    fn list_issues_redmine_did_not_answer() {
+        let processed_time_entries = process_time_entries(get_raw_time_entries());
+
+        let report = generate_report_by_user(processed_time_entries, &HashMap::new());
+
+        assert!(report.starts_with("* **#4**\n\n  Note 2  \n"));
+    }
+
+    #[tokio::test]
+    async fn map_redmine_errors() {
+        use axum::http::StatusCode;
+        use tonic::Code;
+
+        use crate::model::mock::MockRedmine;
+
+        let code = |err| redmine_error("fetch users", err).code();
+        assert_eq!(
+            code(redmine::UnknownUser("jdoe".to_string()).into()),
+            Code::NotFound
+        );
+        assert_eq!(
+            code(redmine::DeadlineExceeded.into()),
+            Code::DeadlineExceeded
+        );
+
+        let mock = MockRedmine::start(|request| {
+            let status = match request.api_key.as_deref() {
+                Some("expired") => StatusCode::UNAUTHORIZED,
+                Some("user") => StatusCode::FORBIDDEN,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, serde_json::json!({}))
+        });
+        let mock = &mock;
+        let fetch = |api_key| async move {
+            let err = mock.redmine(Some(api_key)).get_users().await.unwrap_err();
+            redmine_error("fetch users", err)
+        };
+
+        let status = fetch("expired").await;
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert!(status.message().starts_with("fetch users: "));
+        assert_eq!(fetch("user").await.code(), Code::PermissionDenied);
+        assert_eq!(fetch("admin").await.code(), Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn impersonate_each_user() {
+        use axum::http::StatusCode;
+        use serde_json::json;
+
+        use crate::model::mock::MockRedmine;
+
+        let mock = MockRedmine::start(|request| match request.path.strip_prefix("users/") {
+            Some(user) => {
+                let user_id = user.trim_end_matches(".json");
+                let login = format!("user{}", user_id);
+                (
+                    StatusCode::OK,
+                    json!({"user": {"id": user_id.parse::<u64>().unwrap(), "login": login}}),
+                )
+            }
+            None => (
+                StatusCode::OK,
+                json!({"time_entries": [], "total_count": 0}),
+            ),
+        });
+        let filter = TimeEntryFilter::new(
+            Date::from_calendar_date(2021, Month::January, 4).unwrap(),
+            Date::from_calendar_date(2021, Month::January, 8).unwrap(),
+        );
+
+        aggregate_report(&mock.redmine(Some("admin")), &[3, 7], &filter, true)
+            .await
+            .unwrap();
+
+        let mut switched = mock
+            .requests()
+            .into_iter()
+            .filter(|request| request.path.starts_with("time_entries"))
+            .map(|request| {
+                let user_id = request
+                    .query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("user_id="))
+                    .unwrap()
+                    .to_string();
+                (user_id, request.switch_user, request.api_key)
+            })
+            .collect::<Vec<_>>();
+        switched.sort();
+        assert_eq!(
+            switched,
+            vec![
+                (
+                    "3".to_string(),
+                    Some("user3".to_string()),
+                    Some("admin".to_string())
+                ),
+                (
+                    "7".to_string(),
+                    Some("user7".to_string()),
+                    Some("admin".to_string())
+                ),
+            ]
+        );
+        assert!(mock
+            .requests()
+            .iter()
+            .filter(|request| request.path.starts_with("users/"))
+            .all(|request| request.switch_user.is_none()));
+    }
 }